@@ -0,0 +1,47 @@
+//! Functionality specific to Alpine Linux.
+
+use spurs::{cmd, SshCommand};
+
+/// Install the given list of packages via `apk add`. Requires `sudo` priveleges.
+pub fn apk_add(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo apk add {}", pkgs.join(" "))
+}
+
+/// Remove the given list of packages via `apk del`. Requires `sudo` priveleges.
+pub fn apk_del(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo apk del {}", pkgs.join(" "))
+}
+
+/// Update the index of available packages via `apk update`. Requires `sudo` priveleges.
+pub fn apk_update() -> SshCommand {
+    cmd!("sudo apk update")
+}
+
+#[cfg(test)]
+mod test {
+    use spurs::SshCommand;
+
+    #[test]
+    fn test_apk_add() {
+        assert_eq!(
+            super::apk_add(&["foo", "bar"]),
+            SshCommand::make_cmd("sudo apk add foo bar", None, false, false, false, false),
+        );
+    }
+
+    #[test]
+    fn test_apk_del() {
+        assert_eq!(
+            super::apk_del(&["foobar"]),
+            SshCommand::make_cmd("sudo apk del foobar", None, false, false, false, false),
+        );
+    }
+
+    #[test]
+    fn test_apk_update() {
+        assert_eq!(
+            super::apk_update(),
+            SshCommand::make_cmd("sudo apk update", None, false, false, false, false),
+        );
+    }
+}
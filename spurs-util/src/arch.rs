@@ -0,0 +1,48 @@
+//! Functionality specific to Arch Linux.
+
+use spurs::{cmd, SshCommand};
+
+/// Install the given list of packages via `pacman -S`. Requires `sudo` privileges.
+pub fn pacman_install(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo pacman -S --noconfirm {}", pkgs.join(" "))
+}
+
+/// Remove the given list of packages via `pacman -R`. Requires `sudo` privileges.
+pub fn pacman_remove(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo pacman -R --noconfirm {}", pkgs.join(" "))
+}
+
+#[cfg(test)]
+mod test {
+    use spurs::SshCommand;
+
+    #[test]
+    fn test_pacman_install() {
+        assert_eq!(
+            super::pacman_install(&["foo", "bar"]),
+            SshCommand::make_cmd(
+                "sudo pacman -S --noconfirm foo bar",
+                None,
+                false,
+                false,
+                false,
+                false
+            ),
+        );
+    }
+
+    #[test]
+    fn test_pacman_remove() {
+        assert_eq!(
+            super::pacman_remove(&["foobar"]),
+            SshCommand::make_cmd(
+                "sudo pacman -R --noconfirm foobar",
+                None,
+                false,
+                false,
+                false,
+                false
+            ),
+        );
+    }
+}
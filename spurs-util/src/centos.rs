@@ -1,6 +1,6 @@
 //! Functionality specific to Centos, RHEL, Amazon Linux, and other related distros.
 
-use spurs::{cmd, SshCommand};
+use spurs::{cmd, escape_for_bash, SshCommand};
 
 /// Install the given .rpm packages via `rpm`. Requires `sudo` priveleges.
 pub fn rpm_install(pkg: &str) -> SshCommand {
@@ -12,6 +12,32 @@ pub fn yum_install(pkgs: &[&str]) -> SshCommand {
     cmd!("sudo yum install -y {}", pkgs.join(" "))
 }
 
+/// Remove the given list of packages via `yum remove`. Requires `sudo` priveleges.
+pub fn yum_remove(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo yum remove -y {}", pkgs.join(" "))
+}
+
+/// Install the given list of packages via `dnf install`, which replaces `yum` on Fedora and RHEL 8+.
+/// Requires `sudo` priveleges.
+pub fn dnf_install(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo dnf install -y {}", pkgs.join(" "))
+}
+
+/// Install the given list of package groups (e.g. `Development Tools`) via `dnf install`. The
+/// groups may optionally already be prefixed with `@`. Requires `sudo` priveleges.
+pub fn dnf_group_install(groups: &[&str]) -> SshCommand {
+    let groups = groups
+        .iter()
+        .map(|group| escape_for_bash(&format!("@{}", group.trim_start_matches('@'))))
+        .collect::<Vec<_>>();
+    cmd!("sudo dnf install -y {}", groups.join(" "))
+}
+
+/// Remove the given list of packages via `dnf remove`. Requires `sudo` priveleges.
+pub fn dnf_remove(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo dnf remove -y {}", pkgs.join(" "))
+}
+
 #[cfg(test)]
 mod test {
     use spurs::SshCommand;
@@ -20,8 +46,16 @@ mod test {
     fn test_rpm_install() {
         assert_eq!(
             super::rpm_install("foobar"),
+            SshCommand::make_cmd("sudo rpm -ivh foobar", None, false, false, false, false),
+        );
+    }
+
+    #[test]
+    fn test_yum_install() {
+        assert_eq!(
+            super::yum_install(&["foobar"]),
             SshCommand::make_cmd(
-                "sudo rpm -ivh foobar".into(),
+                "sudo yum install -y foobar",
                 None,
                 false,
                 false,
@@ -32,11 +66,56 @@ mod test {
     }
 
     #[test]
-    fn test_yum_install() {
+    fn test_dnf_install() {
         assert_eq!(
-            super::yum_install(&["foobar"]),
+            super::dnf_install(&["foo", "bar"]),
+            SshCommand::make_cmd(
+                "sudo dnf install -y foo bar",
+                None,
+                false,
+                false,
+                false,
+                false,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_dnf_group_install() {
+        assert_eq!(
+            super::dnf_group_install(&["Development Tools", "@virtualization"]),
+            SshCommand::make_cmd(
+                "sudo dnf install -y '@Development Tools' @virtualization",
+                None,
+                false,
+                false,
+                false,
+                false,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_yum_remove() {
+        assert_eq!(
+            super::yum_remove(&["foobar"]),
+            SshCommand::make_cmd(
+                "sudo yum remove -y foobar",
+                None,
+                false,
+                false,
+                false,
+                false,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_dnf_remove() {
+        assert_eq!(
+            super::dnf_remove(&["foobar"]),
             SshCommand::make_cmd(
-                "sudo yum install -y foobar".into(),
+                "sudo dnf remove -y foobar",
                 None,
                 false,
                 false,
@@ -10,42 +10,217 @@
 //! There are also some utilities that don't construct or run commands. They are just useful
 //! functions for constructing commands.
 //!
-//! The `centos` and `ubuntu` submodules contain routines specifically useful for those platforms.
+//! The `alpine`, `arch`, `centos`, and `ubuntu` submodules contain routines specifically useful for those platforms.
+//!
+//! The helpers that parse JSON (`get_partitions`, `get_unpartitioned_devs`, `get_mounted_devs`,
+//! `get_dev_sizes`, and `measure_bandwidth`) require the `serde_json` feature, which is enabled by
+//! default. The `serde` feature derives `Serialize` and `Deserialize` for the returned information,
+//! such as `MachineInfo`.
 
 #![doc(html_root_url = "https://docs.rs/spurs-util/0.3.1")]
 
+pub mod alpine;
+pub mod arch;
 pub mod centos;
 pub mod ubuntu;
 
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
+    collections::HashMap,
     net::{IpAddr, ToSocketAddrs},
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use spurs::{cmd, Execute, SshCommand, SshError};
+#[cfg(feature = "serde_json")]
+use std::collections::HashSet;
+
+use log::{debug, warn};
+
+use spurs::{cmd, Execute, SshCommand, SshError, SshShell};
+
+/// A snapshot of a machine's hardware and software, useful for recording the provenance of
+/// experimental results. See `machine_fingerprint`.
+///
+/// Each field contains the raw output of the command used to probe that aspect of the machine, or
+/// `None` if the command failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MachineInfo {
+    /// The contents of `/etc/os-release`.
+    pub os_release: Option<String>,
+
+    /// The output of `uname -a`.
+    pub kernel: Option<String>,
+
+    /// The output of `lscpu`.
+    pub cpu: Option<String>,
+
+    /// The contents of `/proc/meminfo`.
+    pub memory: Option<String>,
+
+    /// The output of `lsblk -J` (JSON).
+    pub block_devices: Option<String>,
+}
+
+/// Memory and swap usage of a machine, in kB. See `get_memory_info`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryInfo {
+    /// Total usable RAM.
+    pub total_kb: u64,
+
+    /// RAM that is completely unused.
+    pub free_kb: u64,
+
+    /// An estimate of how much RAM is available for new applications without swapping. Kernels
+    /// older than 3.14 do not report this.
+    pub available_kb: Option<u64>,
+
+    /// Total swap space.
+    pub swap_total_kb: u64,
+
+    /// Unused swap space.
+    pub swap_free_kb: u64,
+}
+
+/// The CPU topology of a machine. See `get_cpu_info`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuInfo {
+    /// The number of CPU sockets.
+    pub sockets: usize,
+
+    /// The number of physical cores in each socket.
+    pub cores_per_socket: usize,
+
+    /// The number of hardware threads in each core (e.g. 2 with hyperthreading).
+    pub threads_per_core: usize,
+
+    /// The number of CPUs (i.e. hardware threads) that are online.
+    pub online_cpus: usize,
+}
+
+/// A NUMA node of a machine. See `get_numa_topology`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NumaNode {
+    /// The node's id, as used by e.g. `numactl --membind`.
+    pub id: usize,
+
+    /// The CPUs in this node. This is empty for memory-only nodes.
+    pub cpus: Vec<usize>,
+
+    /// The total memory of this node.
+    pub memory_kb: u64,
+}
+
+/// Usage of a mounted filesystem. See `get_disk_usage`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiskUsage {
+    /// The device or other source of the filesystem (e.g. `/dev/sda2` or `tmpfs`).
+    pub source: String,
+
+    /// The total size of the filesystem in bytes.
+    pub size: u64,
+
+    /// The number of bytes used.
+    pub used: u64,
+
+    /// The number of bytes available to unprivileged users.
+    pub avail: u64,
+
+    /// The percentage of the filesystem that is used, if known.
+    pub percent_used: Option<u8>,
+
+    /// Where the filesystem is mounted.
+    pub mountpoint: String,
+}
+
+/// An action to perform on a systemd service. See `systemctl`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SystemctlAction {
+    Start,
+    Stop,
+    Restart,
+    Enable,
+    Disable,
+    Status,
+}
+
+/// A filesystem that can be created with `format_partition` or `mkfs`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsType {
+    Ext4,
+    Xfs,
+    Btrfs,
+}
+
+impl FsType {
+    /// The name of the filesystem type, as used by `mkfs.<name>`, `mount -t`, and `/etc/fstab`.
+    pub fn name(self) -> &'static str {
+        match self {
+            FsType::Ext4 => "ext4",
+            FsType::Xfs => "xfs",
+            FsType::Btrfs => "btrfs",
+        }
+    }
+}
+
+/// A Linux distribution, or family of distributions. See `detect_distro`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Distro {
+    Ubuntu,
+    Debian,
+
+    /// CentOS and other RHEL-like distros (e.g. RHEL, Rocky Linux, AlmaLinux).
+    Centos,
+    Fedora,
+    Arch,
+    Alpine,
+
+    /// Any other distro, with its `ID` from `/etc/os-release`.
+    Other(String),
+}
 
 ///////////////////////////////////////////////////////////////////////////////
 // Common useful routines
 ///////////////////////////////////////////////////////////////////////////////
 
-/// Given a string, properly escape the string so that it can be passed as a command line argument
-/// to bash.
-///
-/// This is useful for passing commands to `bash -c` (e.g. through ssh).
-pub fn escape_for_bash(s: &str) -> String {
-    let mut new = String::with_capacity(s.len());
+pub use spurs::escape_for_bash;
 
-    // Escape every non-alphanumeric character.
-    for c in s.chars() {
-        if c.is_ascii_alphanumeric() {
-            new.push(c);
-        } else {
-            new.push('\\');
-            new.push(c);
-        }
+/// Many files in sysfs list the possible options with the active one in brackets (e.g.
+/// `noop deadline [cfq]`). Returns the active option, if there is one.
+fn parse_bracketed_choice(output: &str) -> Option<&str> {
+    output
+        .split_whitespace()
+        .find(|opt| opt.starts_with('[') && opt.ends_with(']'))
+        .map(|opt| &opt[1..opt.len() - 1])
+}
+
+/// Parse the (trimmed) output `output` of the command `cmd`. In dry run mode, there is no output,
+/// so we just return the default value instead.
+fn parse_output<T: FromStr + Default>(
+    cmd: &str,
+    output: &str,
+    dry_run: bool,
+) -> Result<T, SshError> {
+    if dry_run {
+        return Ok(T::default());
     }
 
-    new
+    output
+        .trim()
+        .parse()
+        .map_err(|_| SshError::UnexpectedOutput {
+            cmd: cmd.to_owned(),
+            output: output.to_owned(),
+        })
+}
+
+/// Returns the error for an invalid argument to a helper, described by `msg`.
+fn invalid_input(msg: String) -> SshError {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, msg).into()
 }
 
 /// Given a host:ip address, return `(host, ip)`.
@@ -68,6 +243,65 @@ pub fn set_cpu_scaling_governor(gov: &str) -> SshCommand {
     cmd!("sudo cpupower frequency-set -g {}", gov)
 }
 
+/// Sets the minimum and/or maximum CPU frequency (in kHz) that the CPU frequency governor may
+/// select. This requires
+/// - `cpupower` to be installed,
+/// - `sudo` priveleges,
+/// - the necessary Linux kernel modules.
+///
+/// # Panics
+///
+/// If neither `min` nor `max` is given.
+pub fn set_cpu_freq(min: Option<u64>, max: Option<u64>) -> SshCommand {
+    assert!(
+        min.is_some() || max.is_some(),
+        "at least one of `min` or `max` must be given"
+    );
+
+    let mut cmd = String::from("sudo cpupower frequency-set");
+    if let Some(min) = min {
+        cmd.push_str(&format!(" -d {}", min));
+    }
+    if let Some(max) = max {
+        cmd.push_str(&format!(" -u {}", max));
+    }
+
+    SshCommand::new(&cmd)
+}
+
+/// Common I/O schedulers in the Linux kernel (both legacy and multiqueue ones).
+const IO_SCHEDULERS: &[&str] = &[
+    "none",
+    "mq-deadline",
+    "kyber",
+    "bfq",
+    "noop",
+    "deadline",
+    "cfq",
+];
+
+/// Sets the I/O scheduler for the given block device (e.g. `sda` or `/dev/sda`). Requires `sudo`
+/// permissions.
+///
+/// # Panics
+///
+/// If `scheduler` is not one of `none`, `mq-deadline`, `kyber`, `bfq`, `noop`, `deadline`, or
+/// `cfq`.
+pub fn set_io_scheduler(device: &str, scheduler: &str) -> SshCommand {
+    assert!(
+        IO_SCHEDULERS.contains(&scheduler),
+        "unknown I/O scheduler: {}",
+        scheduler
+    );
+
+    cmd!(
+        "echo {} | sudo tee /sys/block/{}/queue/scheduler",
+        scheduler,
+        device.trim_start_matches("/dev/")
+    )
+    .use_bash()
+}
+
 /// Turn off the swap device. Requires `sudo` permissions.
 pub fn swapoff(device: &str) -> SshCommand {
     cmd!("sudo swapoff {}", device)
@@ -99,13 +333,46 @@ pub fn create_partition(device: &str) -> SshCommand {
     )
 }
 
+/// Bind mount `source` at `target`, so that the contents of `source` are also visible at
+/// `target`. Requires `sudo` permissions.
+pub fn bind_mount(source: &str, target: &str) -> SshCommand {
+    cmd!(
+        "sudo mount --bind {} {}",
+        escape_for_bash(source),
+        escape_for_bash(target)
+    )
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // Below are utilies that actually run a command. These require a shell as input.
 ///////////////////////////////////////////////////////////////////////////////
 
 /// Formats and mounts the given device as ext4 at the given mountpoint owned by the given user.
-/// The given partition and mountpoint are assumed to be valid (we don't check).  We will assume
-/// quite a few things for simplicity:
+/// This is the same as `format_partition` with `FsType::Ext4`; see there for details.
+///
+/// # Warning!
+///
+/// This can cause data loss and seriously mess up your system. **BE VERY CAREFUL**. Make sure you
+/// are formatting the write partition.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// format_partition_as_ext4(root_shell, "/dev/sda4", "/home/foouser/")?;
+/// ```
+pub fn format_partition_as_ext4<P: AsRef<std::path::Path>>(
+    shell: &impl Execute,
+    dry_run: bool,
+    partition: &str,
+    mount: P,
+    owner: &str,
+) -> Result<(), SshError> {
+    format_partition(shell, partition, mount, owner, FsType::Ext4, dry_run)
+}
+
+/// Formats the given device with the filesystem `fs` and mounts it at the given mountpoint owned
+/// by the given user. The given partition and mountpoint are assumed to be valid (we don't check).
+/// We will assume quite a few things for simplicity:
 /// - the disk _IS_ partitioned, but the partition is not formatted
 /// - the disk should be mounted at the mountpoint, which is a valid directory
 /// - you have `sudo` permissions
@@ -124,42 +391,38 @@ pub fn create_partition(device: &str) -> SshCommand {
 /// # Example
 ///
 /// ```rust,ignore
-/// format_partition_as_ext4(root_shell, "/dev/sda4", "/home/foouser/")?;
+/// format_partition(root_shell, "/dev/sda4", "/home/foouser/", "foouser", FsType::Xfs, false)?;
 /// ```
-pub fn format_partition_as_ext4<P: AsRef<std::path::Path>>(
+pub fn format_partition<P: AsRef<std::path::Path>>(
     shell: &impl Execute,
-    dry_run: bool,
     partition: &str,
     mount: P,
     owner: &str,
+    fs: FsType,
+    dry_run: bool,
 ) -> Result<(), SshError> {
+    let mount = mount.as_ref().display().to_string();
+
     shell.run(cmd!("lsblk").dry_run(dry_run))?;
 
     // Make a filesystem on the first partition
-    shell.run(cmd!("sudo mkfs.ext4 {}", partition).dry_run(dry_run))?;
+    mkfs(shell, partition, fs, dry_run)?;
 
     // Mount the FS in tmp
     shell.run(cmd!("mkdir -p /tmp/tmp_mnt").dry_run(dry_run))?;
-    shell.run(cmd!("sudo mount -t ext4 {} /tmp/tmp_mnt", partition).dry_run(dry_run))?;
+    self::mount(shell, partition, "/tmp/tmp_mnt", fs.name(), dry_run)?;
     shell.run(cmd!("sudo chown {} /tmp/tmp_mnt", owner).dry_run(dry_run))?;
 
     // Copy all existing files
-    shell.run(cmd!("rsync -a {}/ /tmp/tmp_mnt/", mount.as_ref().display()).dry_run(dry_run))?;
+    shell.run(cmd!("rsync -a {}/ /tmp/tmp_mnt/", mount).dry_run(dry_run))?;
 
     // Unmount from tmp
     shell.run(cmd!("sync").dry_run(dry_run))?;
     shell.run(cmd!("sudo umount /tmp/tmp_mnt").dry_run(dry_run))?;
 
     // Mount the FS at `mount`
-    shell.run(
-        cmd!(
-            "sudo mount -t ext4 {} {}",
-            partition,
-            mount.as_ref().display()
-        )
-        .dry_run(dry_run),
-    )?;
-    shell.run(cmd!("sudo chown {} {}", owner, mount.as_ref().display()).dry_run(dry_run))?;
+    self::mount(shell, partition, &mount, fs.name(), dry_run)?;
+    shell.run(cmd!("sudo chown {} {}", owner, mount).dry_run(dry_run))?;
 
     // Add to /etc/fstab
     let uuid = shell
@@ -169,387 +432,3543 @@ pub fn format_partition_as_ext4<P: AsRef<std::path::Path>>(
                 .dry_run(dry_run),
         )?
         .stdout;
-    let uuid = uuid.trim();
+    add_fstab_entry(shell, uuid.trim(), &mount, fs.name(), dry_run)?;
+
+    // Print for info
+    shell.run(cmd!("lsblk").dry_run(dry_run))?;
+
+    Ok(())
+}
+
+/// Make an ext4 filesystem on `partition`. Requires `sudo` permissions.
+///
+/// # Warning!
+///
+/// This will destroy any data on `partition`. **BE VERY CAREFUL**. Make sure you are formatting
+/// the right partition.
+pub fn mkfs_ext4(shell: &impl Execute, partition: &str, dry_run: bool) -> Result<(), SshError> {
+    mkfs(shell, partition, FsType::Ext4, dry_run)
+}
+
+/// Make a filesystem of type `fs` on `partition` with `mkfs.<fs>`. Requires `sudo` permissions.
+///
+/// For xfs and btrfs, `mkfs` is forced (`-f`), since otherwise they refuse to overwrite an existing
+/// filesystem. `mkfs.ext4` is not forced, so it asks for confirmation if `partition` already has a
+/// filesystem.
+///
+/// # Warning!
+///
+/// This will destroy any data on `partition`. **BE VERY CAREFUL**. Make sure you are formatting
+/// the right partition.
+pub fn mkfs(
+    shell: &impl Execute,
+    partition: &str,
+    fs: FsType,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let force = match fs {
+        FsType::Ext4 => "",
+        FsType::Xfs | FsType::Btrfs => " -f",
+    };
+
     shell.run(
         cmd!(
-            r#"echo "{}    {}    ext4    defaults    0    1" | sudo tee -a /etc/fstab"#,
-            uuid,
-            mount.as_ref().display()
+            "sudo mkfs.{}{} {}",
+            fs.name(),
+            force,
+            escape_for_bash(partition)
         )
         .dry_run(dry_run),
     )?;
-
-    // Print for info
-    shell.run(cmd!("lsblk").dry_run(dry_run))?;
-
     Ok(())
 }
 
-/// Returns a list of partitions of the given device. For example, `["sda1", "sda2"]`.
-pub fn get_partitions(
+/// Mount `device`, which has a filesystem of type `fstype` (e.g. `"ext4"`), at `target`. `target`
+/// must be an existing directory. Requires `sudo` permissions.
+///
+/// The mount does not persist across reboots; use `add_fstab_entry` for that.
+pub fn mount(
     shell: &impl Execute,
     device: &str,
+    target: &str,
+    fstype: &str,
     dry_run: bool,
-) -> Result<HashSet<String>, SshError> {
-    Ok(shell
-        .run(cmd!("lsblk -o KNAME {}", device).dry_run(dry_run))?
-        .stdout
-        .lines()
-        .map(|line| line.trim().to_owned())
-        .skip(2)
-        .collect())
+) -> Result<(), SshError> {
+    shell.run(
+        cmd!(
+            "sudo mount -t {} {} {}",
+            fstype,
+            escape_for_bash(device),
+            escape_for_bash(target)
+        )
+        .dry_run(dry_run),
+    )?;
+    Ok(())
 }
 
-/// Returns a list of devices with no partitions. For example, `["sda", "sdb"]`.
-pub fn get_unpartitioned_devs(
+/// Append an entry to `/etc/fstab` so that `uuid_or_dev` is mounted at `mount` on boot with the
+/// default options. `uuid_or_dev` is e.g. `UUID=1fb958bf-...` (as printed by `blkid -o export`)
+/// or a device path. `fstype` is the type of the filesystem, e.g. `"ext4"`. Requires `sudo`
+/// permissions.
+///
+/// The filesystem is checked by `fsck` on boot, except for btrfs, which has no boot-time check.
+///
+/// The entry is appended even if `/etc/fstab` already contains an entry for `mount`.
+pub fn add_fstab_entry(
     shell: &impl Execute,
+    uuid_or_dev: &str,
+    mount: &str,
+    fstype: &str,
     dry_run: bool,
-) -> Result<HashSet<String>, SshError> {
-    // List all devs
-    let lsblk = shell.run(cmd!("lsblk -o KNAME").dry_run(dry_run))?.stdout;
-    let mut devices: BTreeSet<&str> = lsblk.lines().map(|line| line.trim()).skip(1).collect();
-
-    // Get the partitions of each device.
-    let partitions: HashMap<_, _> = devices
-        .iter()
-        .map(|&dev| {
-            (
-                dev,
-                get_partitions(shell, &format!("/dev/{}", dev), dry_run),
-            )
-        })
-        .collect();
-
-    // Remove partitions and partitioned devices from the list of devices
-    for (dev, parts) in partitions.into_iter() {
-        let parts = parts?;
-        if !parts.is_empty() {
-            devices.remove(dev);
-            for part in parts {
-                devices.remove(part.as_str());
-            }
-        }
-    }
+) -> Result<(), SshError> {
+    let fsck_pass = if fstype == FsType::Btrfs.name() { 0 } else { 1 };
 
-    Ok(devices.iter().map(|&dev| dev.to_owned()).collect())
+    shell.run(
+        cmd!(
+            r#"echo "{}    {}    {}    defaults    0    {}" | sudo tee -a /etc/fstab"#,
+            uuid_or_dev,
+            mount,
+            fstype,
+            fsck_pass
+        )
+        .dry_run(dry_run),
+    )?;
+    Ok(())
 }
 
-/// Returns the list of devices mounted and their mountpoints. For example, `[("sda2", "/")]`.
-pub fn get_mounted_devs(
+/// Create the directory `path` (and any missing parents) if it does not exist, then optionally set
+/// its `owner` (e.g. `"alice"` or `"alice:users"`) and its `mode` (e.g. `"755"` or `"g+w"`). This is
+/// safe to call repeatedly. Setting the owner requires `sudo` permissions, so if an `owner` is
+/// given, `chmod` is also run with `sudo`.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// ensure_dir(shell, "/data/results", Some("alice"), Some("755"), false)?;
+/// ```
+pub fn ensure_dir(
     shell: &impl Execute,
+    path: &str,
+    owner: Option<&str>,
+    mode: Option<&str>,
     dry_run: bool,
-) -> Result<Vec<(String, String)>, SshError> {
-    let devices = shell
-        .run(cmd!("lsblk -o KNAME,MOUNTPOINT").dry_run(dry_run))?
-        .stdout;
-    let devices = devices.lines().skip(1);
-    let mut mounted = vec![];
-    for line in devices {
-        let split: Vec<_> = line
-            .split(char::is_whitespace)
-            .filter(|s| !s.is_empty())
-            .collect();
+) -> Result<(), SshError> {
+    let path = escape_for_bash(path);
 
-        // Need to make sure there are no duplicates (which can happen with LVM)
-        if split.len() > 1 && mounted.iter().all(|(d, _)| d != split[0]) {
-            mounted.push((split[0].to_owned(), split[1].to_owned()));
-        }
+    shell.run(cmd!("mkdir -p {}", path).dry_run(dry_run))?;
+
+    if let Some(owner) = owner {
+        shell.run(cmd!("sudo chown {} {}", escape_for_bash(owner), path).dry_run(dry_run))?;
     }
-    Ok(mounted)
+
+    if let Some(mode) = mode {
+        let sudo = if owner.is_some() { "sudo " } else { "" };
+        shell.run(cmd!("{}chmod {} {}", sudo, escape_for_bash(mode), path).dry_run(dry_run))?;
+    }
+
+    Ok(())
 }
 
-/// Returns the human-readable size of the devices `devs`. For example, `["477G", "500M"]`.
-pub fn get_dev_sizes(
+/// Format `device` as a swap device with `mkswap` and enable it. If `fstab` is `true`, also add an
+/// entry to `/etc/fstab` so that the swap device is enabled on boot, unless the same entry is
+/// already there (see `ensure_line_in_file`). Requires `sudo` permissions.
+///
+/// # Warning!
+///
+/// `mkswap` will destroy any data on `device`. **BE VERY CAREFUL**. Make sure you are formatting
+/// the right device.
+pub fn setup_swap_device(
     shell: &impl Execute,
-    devs: Vec<&str>,
+    device: &str,
+    fstab: bool,
     dry_run: bool,
-) -> Result<Vec<String>, SshError> {
-    let per_dev = devs
-        .iter()
-        .map(|dev| shell.run(cmd!("lsblk -o SIZE /dev/{}", dev).dry_run(dry_run)));
+) -> Result<(), SshError> {
+    let device = escape_for_bash(device);
 
-    let mut sizes = vec![];
-    for size in per_dev {
-        sizes.push(size?.stdout.lines().nth(1).unwrap().trim().to_owned());
+    shell.run(cmd!("sudo mkswap {}", device).dry_run(dry_run))?;
+    shell.run(swapon(&device).dry_run(dry_run))?;
+
+    if fstab {
+        let uuid = shell
+            .run(
+                cmd!("sudo blkid -o export {} | grep '^UUID='", device)
+                    .use_bash()
+                    .dry_run(dry_run),
+            )?
+            .stdout;
+        let entry = format!("{}    none    swap    sw    0    0", uuid.trim());
+        ensure_line_in_file(shell, &entry, "/etc/fstab", true, dry_run)?;
     }
 
-    Ok(sizes)
+    Ok(())
 }
 
-/// Reboot and wait for the remote machine to come back up again. Requires `sudo`.
-pub fn reboot(shell: &mut impl Execute, dry_run: bool) -> Result<(), SshError> {
-    let _ = shell.run(cmd!("sudo reboot").dry_run(dry_run));
-
-    if !dry_run {
-        // If we try to reconnect immediately, the machine will not have gone down yet.
-        std::thread::sleep(std::time::Duration::from_secs(10));
-
-        // Attempt to reconnect.
-        shell.reconnect()?;
+/// Returns an error if `username` is not a valid username (see `create_user`). Usernames are
+/// validated rather than escaped because `~<username>` must stay unquoted for the shell to expand
+/// it.
+fn check_username(username: &str) -> Result<(), SshError> {
+    if username.is_empty()
+        || username.starts_with('-')
+        || !username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+    {
+        return Err(invalid_input(format!("invalid username: {:?}", username)));
     }
 
-    // Make sure it worked.
-    shell.run(cmd!("whoami").dry_run(dry_run))?;
-
     Ok(())
 }
 
-///////////////////////////////////////////////////////////////////////////////
-// Tests
-///////////////////////////////////////////////////////////////////////////////
-
-#[cfg(test)]
-mod test {
-    use log::info;
+/// Create the user `username` via `useradd`. If `create_home` is `true`, a home directory is
+/// created. If `shell_path` is given, it is used as the user's login shell. If the user already
+/// exists, this does nothing. Requires `sudo` permissions.
+///
+/// `username` may only contain ASCII letters, digits, `_`, `-`, and `.`, and must not start with
+/// `-`. Otherwise, an `SshError::IoError` of kind `InvalidInput` is returned without running
+/// anything.
+pub fn create_user(
+    shell: &impl Execute,
+    username: &str,
+    create_home: bool,
+    shell_path: Option<&str>,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    /// The exit code of `useradd` when the username is already in use.
+    const USERADD_USER_EXISTS: i32 = 9;
 
-    use spurs::{Execute, SshCommand, SshError, SshOutput};
+    check_username(username)?;
 
-    /// An `Execute` implementation for use in tests.
-    #[derive(Clone, Debug)]
-    pub struct TestSshShell {
-        pub commands: std::sync::Arc<std::sync::Mutex<Vec<SshCommand>>>,
+    let mut cmd = String::from("sudo useradd");
+    if create_home {
+        cmd.push_str(" -m");
+    }
+    if let Some(shell_path) = shell_path {
+        cmd.push_str(&format!(" -s {}", escape_for_bash(shell_path)));
     }
 
-    impl TestSshShell {
-        pub fn new() -> Self {
-            // init logging if never done before...
-            use std::sync::Once;
-            static START: Once = Once::new();
-            START.call_once(|| {
-                env_logger::init();
-            });
-
-            Self {
-                commands: std::sync::Arc::new(std::sync::Mutex::new(vec![])),
-            }
+    match shell.run(cmd!("{} {}", cmd, username).dry_run(dry_run)) {
+        Ok(_) => Ok(()),
+        Err(SshError::NonZeroExit {
+            exit: USERADD_USER_EXISTS,
+            ..
+        }) => {
+            warn!("User {} already exists.", username);
+            Ok(())
         }
+        Err(e) => Err(e),
     }
+}
 
-    impl Execute for TestSshShell {
-        fn run(&self, cmd: SshCommand) -> Result<SshOutput, SshError> {
-            info!("Test run({:#?})", cmd);
+/// Set the password of the user `username` via `chpasswd`. The password is passed on stdin, so it
+/// is not printed with the command. Requires `sudo` permissions.
+pub fn set_user_password(
+    shell: &impl Execute,
+    username: &str,
+    password: &str,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    shell.run(
+        cmd!("sudo chpasswd")
+            .stdin(format!("{}:{}\n", username, password))
+            .dry_run(dry_run),
+    )?;
 
-            enum FakeCommand {
-                Blkid,
-                Kname1,
-                Kname2,
-                Kname3,
-                Kname4,
-                KnameMountpoint,
-                Size1,
-                Size2,
-                Size3,
-                Unknown,
-            }
+    Ok(())
+}
 
-            let short_cmd = {
-                if cmd.cmd().contains("blkid") {
-                    FakeCommand::Blkid
-                } else if cmd.cmd().contains("KNAME /dev/foobar") {
-                    FakeCommand::Kname1
-                } else if cmd.cmd().contains("KNAME /dev/sd") {
-                    FakeCommand::Kname3
-                } else if cmd.cmd().contains("KNAME /dev/") {
-                    FakeCommand::Kname4
-                } else if cmd.cmd().contains("KNAME,MOUNTPOINT") {
-                    FakeCommand::KnameMountpoint
-                } else if cmd.cmd().contains("KNAME") {
-                    FakeCommand::Kname2
-                } else if cmd.cmd().contains("SIZE /dev/sda") {
-                    FakeCommand::Size1
-                } else if cmd.cmd().contains("SIZE /dev/sdb") {
-                    FakeCommand::Size2
-                } else if cmd.cmd().contains("SIZE /dev/sdc") {
-                    FakeCommand::Size3
-                } else {
-                    FakeCommand::Unknown
-                }
-            };
+/// Add `pubkey` to the `~/.ssh/authorized_keys` of `user`, or of the executing user if `user` is
+/// `None`. The key is not added again if it is already present. `~/.ssh` and `authorized_keys` are
+/// created if needed, with the permissions required by `sshd`. Modifying another user's keys
+/// requires `sudo` permissions. An invalid `user` is rejected as in `create_user`.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let pubkey = std::fs::read_to_string("/home/me/.ssh/id_ed25519.pub")?;
+/// add_authorized_key(shell, &pubkey, Some("alice"), false)?;
+/// ```
+pub fn add_authorized_key(
+    shell: &impl Execute,
+    pubkey: &str,
+    user: Option<&str>,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    if let Some(user) = user {
+        check_username(user)?;
+    }
+
+    let (sudo, ssh_dir) = match user {
+        Some(user) => ("sudo ", format!("~{}/.ssh", user)),
+        None => ("", "~/.ssh".to_owned()),
+    };
+    let keys = format!("{}/authorized_keys", ssh_dir);
+    let pubkey = escape_for_bash(pubkey.trim());
+
+    shell.run(cmd!("{}mkdir -p {}", sudo, ssh_dir).dry_run(dry_run))?;
+    shell.run(cmd!("{}chmod 700 {}", sudo, ssh_dir).dry_run(dry_run))?;
+
+    // `grep` also fails if `authorized_keys` doesn't exist yet, in which case we create it.
+    let present = !dry_run
+        && match shell.run(cmd!("{}grep -qxF {} {}", sudo, pubkey, keys)) {
+            Ok(_) => true,
+            Err(SshError::NonZeroExit { .. }) => false,
+            Err(e) => return Err(e),
+        };
+    if !present {
+        shell.run(
+            cmd!("echo {} | {}tee -a {}", pubkey, sudo, keys)
+                .use_bash()
+                .dry_run(dry_run),
+        )?;
+    }
+
+    shell.run(cmd!("{}chmod 600 {}", sudo, keys).dry_run(dry_run))?;
+    if let Some(user) = user {
+        shell.run(cmd!("sudo chown -R {}: {}", user, ssh_dir).dry_run(dry_run))?;
+    }
+
+    Ok(())
+}
+
+/// Set the hostname of the remote machine to `hostname`, via `hostnamectl` if available or
+/// `hostname` otherwise. `/etc/hostname` and, on Debian-style systems, the `127.0.1.1` line of
+/// `/etc/hosts` are also updated so that the change persists across reboots. Requires `sudo`
+/// permissions.
+///
+/// `hostname` may only contain ASCII letters, digits, `-`, and `.`, and must not start with `-`.
+/// Otherwise, an `SshError::IoError` of kind `InvalidInput` is returned without running anything.
+pub fn set_hostname(shell: &impl Execute, hostname: &str, dry_run: bool) -> Result<(), SshError> {
+    if hostname.is_empty()
+        || hostname.starts_with('-')
+        || !hostname
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        return Err(invalid_input(format!("invalid hostname: {:?}", hostname)));
+    }
+
+    shell.run(
+        cmd!(
+            "if command -v hostnamectl >/dev/null; then sudo hostnamectl set-hostname {0}; \
+             else sudo hostname {0}; fi",
+            hostname
+        )
+        .use_bash()
+        .dry_run(dry_run),
+    )?;
+    shell.run(cmd!("echo {} | sudo tee /etc/hostname", hostname).dry_run(dry_run))?;
+    shell.run(
+        cmd!(
+            r"sudo sed -i 's/^127\.0\.1\.1\s.*/127.0.1.1\t{}/' /etc/hosts",
+            hostname
+        )
+        .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// Initialize `devices` as LVM physical volumes and create the volume group `vg` from them.
+/// Requires `sudo` permissions.
+///
+/// # Warning!
+///
+/// This will destroy any data on `devices`. **BE VERY CAREFUL**.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// lvm_create_vg(shell, "data", &["/dev/sdb", "/dev/sdc"], false)?;
+/// lvm_create_lv(shell, "data", "scratch", "100%FREE", false)?;
+/// ```
+pub fn lvm_create_vg(
+    shell: &impl Execute,
+    vg: &str,
+    devices: &[&str],
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let devices = devices
+        .iter()
+        .map(|device| escape_for_bash(device))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    shell.run(cmd!("sudo pvcreate {}", devices).dry_run(dry_run))?;
+    shell.run(cmd!("sudo vgcreate {} {}", escape_for_bash(vg), devices).dry_run(dry_run))?;
+
+    Ok(())
+}
+
+/// Create the logical volume `lv` in the volume group `vg`. `size` is either an absolute size
+/// (e.g. `100G`) or a percentage (e.g. `50%VG` or `100%FREE`). Requires `sudo` permissions.
+pub fn lvm_create_lv(
+    shell: &impl Execute,
+    vg: &str,
+    lv: &str,
+    size: &str,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    // `lvcreate` takes percentages as a number of extents (`-l`) rather than a size (`-L`).
+    let size_flag = if size.contains('%') { "-l" } else { "-L" };
+
+    shell.run(
+        cmd!(
+            "sudo lvcreate {} {} -n {} {}",
+            size_flag,
+            escape_for_bash(size),
+            escape_for_bash(lv),
+            escape_for_bash(vg)
+        )
+        .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// A block device, as listed by `lsblk -J`. See `lsblk`.
+#[cfg(feature = "serde_json")]
+#[derive(Debug, serde::Deserialize)]
+struct BlockDevice {
+    /// The kernel name of the device (e.g. `sda1` or `dm-0`).
+    #[serde(rename = "kname")]
+    name: String,
+
+    /// The type of the device (e.g. `disk`, `part`, or `lvm`).
+    #[serde(rename = "type")]
+    ty: String,
+
+    /// The human-readable size of the device (e.g. `477G`).
+    size: String,
+
+    /// Where the device is mounted, if anywhere.
+    mountpoint: Option<String>,
+
+    /// The devices on top of this one (e.g. its partitions).
+    #[serde(default)]
+    children: Vec<BlockDevice>,
+}
+
+#[cfg(feature = "serde_json")]
+impl BlockDevice {
+    /// Returns this device and all of its descendants, in the order that `lsblk` lists them.
+    fn walk(&self) -> Vec<&BlockDevice> {
+        let mut devices = vec![self];
+        for child in &self.children {
+            devices.extend(child.walk());
+        }
+        devices
+    }
+
+    /// Returns the partitions among the descendants of this device.
+    fn partitions(&self) -> Vec<&BlockDevice> {
+        self.walk()
+            .into_iter()
+            .skip(1)
+            .filter(|dev| dev.ty == "part")
+            .collect()
+    }
+}
+
+/// The output of `lsblk -J`.
+#[cfg(feature = "serde_json")]
+#[derive(serde::Deserialize)]
+struct Lsblk {
+    blockdevices: Vec<BlockDevice>,
+}
+
+/// Returns the tree of block devices under `device` (e.g. `/dev/sda`), or of all block devices if
+/// `device` is `None`, as listed by `lsblk`. If `device` is given, it is the first (and only) root
+/// of the tree. In dry run mode, this returns no devices.
+#[cfg(feature = "serde_json")]
+fn lsblk(
+    shell: &impl Execute,
+    device: Option<&str>,
+    dry_run: bool,
+) -> Result<Vec<BlockDevice>, SshError> {
+    // Parsing JSON, rather than columns of text, doesn't depend on the tree glyphs or the default
+    // columns of the particular version of `lsblk`.
+    let cmd = match device {
+        Some(device) => cmd!(
+            "lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT {}",
+            escape_for_bash(device)
+        ),
+        None => cmd!("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT"),
+    };
+    let raw = cmd.cmd().to_owned();
+    let output = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    if dry_run {
+        return Ok(vec![]);
+    }
+
+    match serde_json::from_str::<Lsblk>(&output) {
+        Ok(lsblk) if device.is_none() || !lsblk.blockdevices.is_empty() => Ok(lsblk.blockdevices),
+        _ => Err(SshError::UnexpectedOutput { cmd: raw, output }),
+    }
+}
+
+/// Returns a list of partitions of the given device. For example, `["sda1", "sda2"]`.
+#[cfg(feature = "serde_json")]
+pub fn get_partitions(
+    shell: &impl Execute,
+    device: &str,
+    dry_run: bool,
+) -> Result<HashSet<String>, SshError> {
+    Ok(lsblk(shell, Some(device), dry_run)?
+        .iter()
+        .flat_map(BlockDevice::partitions)
+        .map(|part| part.name.clone())
+        .collect())
+}
+
+/// Returns a list of devices with no partitions. For example, `["sda", "sdb"]`.
+#[cfg(feature = "serde_json")]
+pub fn get_unpartitioned_devs(
+    shell: &impl Execute,
+    dry_run: bool,
+) -> Result<HashSet<String>, SshError> {
+    let roots = lsblk(shell, None, dry_run)?;
+    let all: Vec<_> = roots.iter().flat_map(BlockDevice::walk).collect();
+    let mut devices: HashSet<_> = all.iter().map(|dev| dev.name.as_str()).collect();
+
+    // Remove partitions and partitioned devices from the list of devices
+    for dev in all {
+        let parts = dev.partitions();
+        if !parts.is_empty() {
+            devices.remove(dev.name.as_str());
+            for part in parts {
+                devices.remove(part.name.as_str());
+            }
+        }
+    }
+
+    Ok(devices.into_iter().map(str::to_owned).collect())
+}
+
+/// Returns the list of devices mounted and their mountpoints. For example, `[("sda2", "/")]`.
+#[cfg(feature = "serde_json")]
+pub fn get_mounted_devs(
+    shell: &impl Execute,
+    dry_run: bool,
+) -> Result<Vec<(String, String)>, SshError> {
+    let roots = lsblk(shell, None, dry_run)?;
+    let mut mounted: Vec<(String, String)> = vec![];
+    for dev in roots.iter().flat_map(BlockDevice::walk) {
+        // Need to make sure there are no duplicates (which can happen with LVM)
+        if let Some(mountpoint) = &dev.mountpoint {
+            if mounted.iter().all(|(d, _)| *d != dev.name) {
+                mounted.push((dev.name.clone(), mountpoint.clone()));
+            }
+        }
+    }
+    Ok(mounted)
+}
+
+/// Returns where `device` (e.g. `/dev/sdb1`) is mounted, or `None` if it is not mounted, according
+/// to `findmnt`. If it is mounted in multiple places, the first one is returned. In dry run mode,
+/// this always returns `None`.
+pub fn get_mount_point(
+    shell: &impl Execute,
+    device: &str,
+    dry_run: bool,
+) -> Result<Option<String>, SshError> {
+    let out = match shell.run(
+        cmd!("findmnt -n -o TARGET {}", escape_for_bash(device))
+            .allow_error()
+            .dry_run(dry_run),
+    ) {
+        Ok(out) if out.exit_status == 0 => out.stdout,
+        Ok(_) | Err(SshError::NonZeroExit { .. }) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    Ok(out
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_owned))
+}
+
+/// Returns `true` if `device` (e.g. `/dev/sdb1`) is mounted anywhere. See `get_mount_point`. To
+/// check whether a path is a mountpoint instead, use `is_mountpoint`.
+pub fn is_mounted(shell: &impl Execute, device: &str, dry_run: bool) -> Result<bool, SshError> {
+    Ok(get_mount_point(shell, device, dry_run)?.is_some())
+}
+
+/// Unmount `target`, which can be a device or a mountpoint. If `target` is not mounted, this does
+/// nothing. Requires `sudo` permissions.
+///
+/// If `force` is set, any processes using the filesystem are killed with `fuser -km` first, and
+/// `umount -f` is used. If `lazy` is set, `umount -l` detaches the filesystem right away and cleans
+/// up once it is no longer busy.
+pub fn unmount(
+    shell: &impl Execute,
+    target: &str,
+    force: bool,
+    lazy: bool,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    if !dry_run && get_mount_point(shell, target, dry_run)?.is_none() {
+        debug!("{} is not mounted.", target);
+        return Ok(());
+    }
+
+    let target = escape_for_bash(target);
+
+    if force {
+        // `fuser` fails if no processes use the filesystem.
+        shell.run(
+            cmd!("sudo fuser -km {}", target)
+                .allow_error()
+                .dry_run(dry_run),
+        )?;
+    }
+
+    let mut flags = String::new();
+    if force {
+        flags.push_str(" -f");
+    }
+    if lazy {
+        flags.push_str(" -l");
+    }
+    shell.run(cmd!("sudo umount{} {}", flags, target).dry_run(dry_run))?;
+
+    Ok(())
+}
+
+/// Returns the usage of each mounted filesystem, as reported by `df`. In dry run mode, this returns
+/// an empty `Vec`.
+pub fn get_disk_usage(shell: &impl Execute, dry_run: bool) -> Result<Vec<DiskUsage>, SshError> {
+    let cmd = cmd!("df -B1 --output=source,size,used,avail,pcent,target");
+    let raw = cmd.cmd().to_owned();
+    let df = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    df.lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            parse_df_line(line).ok_or_else(|| SshError::UnexpectedOutput {
+                cmd: raw.clone(),
+                output: df.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Parses one line of the output of `df --output=source,size,used,avail,pcent,target`.
+fn parse_df_line(line: &str) -> Option<DiskUsage> {
+    // The mountpoint is last and may contain spaces, so we take the first five columns and
+    // treat the rest of the line as the mountpoint.
+    let mut cols = vec![];
+    let mut rest = line.trim();
+    for _ in 0..5 {
+        let end = rest.find(char::is_whitespace)?;
+        cols.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+
+    Some(DiskUsage {
+        source: cols[0].to_owned(),
+        size: cols[1].parse().ok()?,
+        used: cols[2].parse().ok()?,
+        avail: cols[3].parse().ok()?,
+        // Some pseudo-filesystems report `-`.
+        percent_used: cols[4].trim_end_matches('%').parse().ok(),
+        mountpoint: rest.to_owned(),
+    })
+}
+
+/// Returns the human-readable size of the devices `devs`. For example, `["477G", "500M"]`. In dry
+/// run mode, the sizes are empty strings.
+#[cfg(feature = "serde_json")]
+pub fn get_dev_sizes(
+    shell: &impl Execute,
+    devs: Vec<&str>,
+    dry_run: bool,
+) -> Result<Vec<String>, SshError> {
+    let mut sizes = vec![];
+    for dev in devs {
+        match lsblk(shell, Some(&format!("/dev/{}", dev)), dry_run)?.pop() {
+            Some(dev) => sizes.push(dev.size),
+            // Only in dry run mode.
+            None => sizes.push(String::new()),
+        }
+    }
+
+    Ok(sizes)
+}
+
+/// Returns the memory and swap usage of the remote machine, as reported by `/proc/meminfo`. In dry
+/// run mode, this returns `MemoryInfo::default()`.
+pub fn get_memory_info(shell: &impl Execute, dry_run: bool) -> Result<MemoryInfo, SshError> {
+    let cmd = cmd!("cat /proc/meminfo");
+    let raw = cmd.cmd().to_owned();
+    let meminfo = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    if dry_run {
+        return Ok(MemoryInfo::default());
+    }
+
+    // Each line looks like `MemTotal:       16318340 kB`.
+    let fields: HashMap<&str, u64> = meminfo
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?.trim_end_matches(':');
+            let value = parts.next()?.parse().ok()?;
+            Some((name, value))
+        })
+        .collect();
+
+    let field = |name| {
+        fields
+            .get(name)
+            .copied()
+            .ok_or_else(|| SshError::UnexpectedOutput {
+                cmd: raw.clone(),
+                output: meminfo.clone(),
+            })
+    };
+
+    Ok(MemoryInfo {
+        total_kb: field("MemTotal")?,
+        free_kb: field("MemFree")?,
+        available_kb: fields.get("MemAvailable").copied(),
+        swap_total_kb: field("SwapTotal")?,
+        swap_free_kb: field("SwapFree")?,
+    })
+}
+
+/// Returns the CPU topology of the remote machine, as reported by `lscpu`. In dry run mode, this
+/// returns `CpuInfo::default()`.
+///
+/// We parse the plain-text output of `lscpu` rather than its JSON output (`lscpu -J`) because
+/// older versions of `lscpu` don't support JSON, and newer versions nest the JSON fields.
+pub fn get_cpu_info(shell: &impl Execute, dry_run: bool) -> Result<CpuInfo, SshError> {
+    // Field names are localized, so make sure we get the English ones.
+    let cmd = cmd!("LC_ALL=C lscpu");
+    let raw = cmd.cmd().to_owned();
+    let lscpu = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    if dry_run {
+        return Ok(CpuInfo::default());
+    }
+
+    // Each line looks like `Core(s) per socket:  4`.
+    let fields: HashMap<&str, &str> = lscpu
+        .lines()
+        .filter_map(|line| {
+            let colon = line.find(':')?;
+            Some((line[..colon].trim(), line[colon + 1..].trim()))
+        })
+        .collect();
+
+    let unexpected = || SshError::UnexpectedOutput {
+        cmd: raw.clone(),
+        output: lscpu.clone(),
+    };
+    let field = |name| {
+        fields
+            .get(name)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(unexpected)
+    };
+
+    Ok(CpuInfo {
+        sockets: field("Socket(s)")?,
+        cores_per_socket: field("Core(s) per socket")?,
+        threads_per_core: field("Thread(s) per core")?,
+        online_cpus: fields
+            .get("On-line CPU(s) list")
+            .and_then(|list| count_cpu_list(list))
+            .ok_or_else(unexpected)?,
+    })
+}
+
+/// Counts the CPUs in a list like `0-3,8,10-11`, returning `None` if the list is malformed.
+fn count_cpu_list(list: &str) -> Option<usize> {
+    parse_cpu_list(list).map(|cpus| cpus.len())
+}
+
+/// Expands a list of CPUs like `0-3,8,10-11`, returning `None` if the list is malformed.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = vec![];
+    for range in list.split(',') {
+        let mut ends = range.trim().splitn(2, '-');
+        let start: usize = ends.next()?.parse().ok()?;
+        let end: usize = match ends.next() {
+            Some(end) => end.parse().ok()?,
+            None => start,
+        };
+        if end < start {
+            return None;
+        }
+        cpus.extend(start..=end);
+    }
+    Some(cpus)
+}
+
+/// Returns the NUMA nodes of the remote machine, with their CPUs and memory, sorted by id. On
+/// machines without NUMA support, this returns a single node with all online CPUs and all memory.
+/// In dry run mode, this returns an empty `Vec`.
+pub fn get_numa_topology(shell: &impl Execute, dry_run: bool) -> Result<Vec<NumaNode>, SshError> {
+    // `grep -H` prints the file name too, so we can tell which node a list belongs to. Memory-only
+    // nodes have an empty list, which `grep .` skips.
+    let cmd = cmd!("grep -H . /sys/devices/system/node/node*/cpulist");
+    let raw = cmd.cmd().to_owned();
+    let cpulists = match shell.run(cmd.dry_run(dry_run)) {
+        Ok(output) => output.stdout,
+
+        // No NUMA support, so there is no `/sys/devices/system/node`.
+        Err(SshError::NonZeroExit { .. }) => return get_single_numa_node(shell, dry_run),
+
+        Err(e) => return Err(e),
+    };
+
+    let unexpected = |raw: &str, output: &str| SshError::UnexpectedOutput {
+        cmd: raw.to_owned(),
+        output: output.to_owned(),
+    };
+
+    let mut cpus = HashMap::new();
+    for line in cpulists.lines() {
+        let (path, list) =
+            line.split_at(line.find(':').ok_or_else(|| unexpected(&raw, &cpulists))?);
+        let id: usize = path
+            .trim_start_matches("/sys/devices/system/node/node")
+            .trim_end_matches("/cpulist")
+            .parse()
+            .map_err(|_| unexpected(&raw, &cpulists))?;
+        let list = parse_cpu_list(&list[1..]).ok_or_else(|| unexpected(&raw, &cpulists))?;
+        cpus.insert(id, list);
+    }
+
+    // Each line looks like `Node 0 MemTotal:       16318340 kB`.
+    let cmd = cmd!("grep -h MemTotal /sys/devices/system/node/node*/meminfo");
+    let raw = cmd.cmd().to_owned();
+    let meminfo = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    let mut nodes = meminfo
+        .lines()
+        .map(
+            |line| match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+                ["Node", id, "MemTotal:", kb, "kB"] => {
+                    let id: usize = id.parse().ok()?;
+                    Some(NumaNode {
+                        id,
+                        cpus: cpus.remove(&id).unwrap_or_default(),
+                        memory_kb: kb.parse().ok()?,
+                    })
+                }
+                _ => None,
+            },
+        )
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| unexpected(&raw, &meminfo))?;
+    nodes.sort_by_key(|node| node.id);
+
+    Ok(nodes)
+}
+
+/// Returns a single NUMA node with all online CPUs and all memory. See `get_numa_topology`.
+fn get_single_numa_node(shell: &impl Execute, dry_run: bool) -> Result<Vec<NumaNode>, SshError> {
+    let cmd = cmd!("cat /sys/devices/system/cpu/online");
+    let raw = cmd.cmd().to_owned();
+    let online = shell.run(cmd.dry_run(dry_run))?.stdout;
+    let memory = get_memory_info(shell, dry_run)?;
+
+    if dry_run {
+        return Ok(vec![]);
+    }
+
+    let cpus = parse_cpu_list(online.trim()).ok_or_else(|| SshError::UnexpectedOutput {
+        cmd: raw,
+        output: online.clone(),
+    })?;
+
+    Ok(vec![NumaNode {
+        id: 0,
+        cpus,
+        memory_kb: memory.total_kb,
+    }])
+}
+
+/// Returns the current frequency (in kHz) of the given CPU, as reported by the kernel's
+/// `scaling_cur_freq`. In dry run mode, this returns 0.
+pub fn get_cpu_freq(shell: &impl Execute, cpu: usize, dry_run: bool) -> Result<u64, SshError> {
+    let cmd = cmd!(
+        "cat /sys/devices/system/cpu/cpu{}/cpufreq/scaling_cur_freq",
+        cpu
+    );
+    let raw = cmd.cmd().to_owned();
+    let freq = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    parse_output(&raw, &freq, dry_run)
+}
+
+/// Returns the CPU scaling governor of each CPU, ordered by CPU number. In dry run mode, this
+/// returns an empty `Vec`.
+pub fn get_cpu_scaling_governor(
+    shell: &impl Execute,
+    dry_run: bool,
+) -> Result<Vec<String>, SshError> {
+    // `grep -H` prints the file name too (even if there is only one CPU), so we can tell which
+    // governor belongs to which CPU. A glob alone would sort `cpu10` before `cpu2`.
+    let cmd = cmd!("grep -H . /sys/devices/system/cpu/cpu*/cpufreq/scaling_governor");
+    let raw = cmd.cmd().to_owned();
+    let out = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    let unexpected = || SshError::UnexpectedOutput {
+        cmd: raw.clone(),
+        output: out.clone(),
+    };
+
+    let mut governors = out
+        .lines()
+        .map(|line| {
+            let (path, gov) = line.split_at(line.find(':').ok_or_else(unexpected)?);
+            let cpu: usize = path
+                .trim_start_matches("/sys/devices/system/cpu/cpu")
+                .trim_end_matches("/cpufreq/scaling_governor")
+                .parse()
+                .map_err(|_| unexpected())?;
+            Ok((cpu, gov[1..].trim().to_owned()))
+        })
+        .collect::<Result<Vec<_>, SshError>>()?;
+    governors.sort();
+
+    Ok(governors.into_iter().map(|(_, gov)| gov).collect())
+}
+
+/// Returns the active I/O scheduler for the given block device (e.g. `sda` or `/dev/sda`). In dry
+/// run mode, this returns an empty string.
+pub fn get_io_scheduler(
+    shell: &impl Execute,
+    device: &str,
+    dry_run: bool,
+) -> Result<String, SshError> {
+    let cmd = cmd!(
+        "cat /sys/block/{}/queue/scheduler",
+        device.trim_start_matches("/dev/")
+    );
+    let raw = cmd.cmd().to_owned();
+    let schedulers = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    if dry_run {
+        return Ok(String::new());
+    }
+
+    match parse_bracketed_choice(&schedulers) {
+        Some(active) => Ok(active.to_owned()),
+        None => Err(SshError::UnexpectedOutput {
+            cmd: raw,
+            output: schedulers,
+        }),
+    }
+}
+
+/// The file controlling transparent huge pages (THP).
+const THP_ENABLED: &str = "/sys/kernel/mm/transparent_hugepage/enabled";
+
+/// Sets the transparent huge page (THP) mode to `always`, `madvise`, or `never`. Requires `sudo`
+/// permissions. Any other `mode` is rejected with an `SshError::IoError` of kind `InvalidInput`,
+/// without running anything.
+pub fn set_transparent_hugepages(
+    shell: &impl Execute,
+    mode: &str,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    if !["always", "madvise", "never"].contains(&mode) {
+        return Err(invalid_input(format!("unknown THP mode: {}", mode)));
+    }
+
+    shell.run(
+        cmd!("echo {} | sudo tee {}", mode, THP_ENABLED)
+            .use_bash()
+            .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// Returns the active transparent huge page (THP) mode (e.g. `never`). In dry run mode, this
+/// returns an empty string.
+pub fn get_transparent_hugepages(shell: &impl Execute, dry_run: bool) -> Result<String, SshError> {
+    let cmd = cmd!("cat {}", THP_ENABLED);
+    let raw = cmd.cmd().to_owned();
+    let modes = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    if dry_run {
+        return Ok(String::new());
+    }
+
+    match parse_bracketed_choice(&modes) {
+        Some(active) => Ok(active.to_owned()),
+        None => Err(SshError::UnexpectedOutput {
+            cmd: raw,
+            output: modes,
+        }),
+    }
+}
+
+/// Sets the number of huge pages of size `size_kb` (e.g. 2048 or 1048576) in the kernel's pool to
+/// `count`. The kernel may be unable to allocate all of them (e.g. if memory is fragmented), in
+/// which case a warning is logged. Requires `sudo` permissions.
+///
+/// 1GB pages usually need to be reserved at boot instead, using the `hugepagesz=1G hugepages=N`
+/// kernel parameters (see `set_kernel_cmdline`).
+pub fn set_hugepages(
+    shell: &impl Execute,
+    size_kb: usize,
+    count: usize,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    shell.run(
+        cmd!(
+            "echo {} | sudo tee {}/nr_hugepages",
+            count,
+            hugepages_dir(size_kb)
+        )
+        .use_bash()
+        .dry_run(dry_run),
+    )?;
+
+    let (total, _) = get_hugepages(shell, size_kb, dry_run)?;
+    if !dry_run && total < count {
+        warn!(
+            "Only allocated {} of {} huge pages of size {}kB.",
+            total, count, size_kb
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the total and free number of huge pages of size `size_kb` in the kernel's pool. In dry
+/// run mode, this returns `(0, 0)`.
+pub fn get_hugepages(
+    shell: &impl Execute,
+    size_kb: usize,
+    dry_run: bool,
+) -> Result<(usize, usize), SshError> {
+    let dir = hugepages_dir(size_kb);
+    let cmd = cmd!("cat {}/nr_hugepages {}/free_hugepages", dir, dir);
+    let raw = cmd.cmd().to_owned();
+    let out = shell.run(cmd.dry_run(dry_run))?.stdout;
+
+    if dry_run {
+        return Ok((0, 0));
+    }
+
+    let counts = out
+        .split_whitespace()
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<usize>>>();
+    match counts.as_deref() {
+        Some(&[total, free]) => Ok((total, free)),
+        _ => Err(SshError::UnexpectedOutput {
+            cmd: raw,
+            output: out,
+        }),
+    }
+}
+
+/// The sysfs directory for huge pages of size `size_kb`.
+fn hugepages_dir(size_kb: usize) -> String {
+    format!("/sys/kernel/mm/hugepages/hugepages-{}kB", size_kb)
+}
+
+/// Load the kernel module `module` with the given `params` (e.g. `["max_loop=64"]`). `module` can
+/// also be the path of a `.ko` file (e.g. a custom module), which is loaded with `insmod`. Loading
+/// a module that is already loaded succeeds without doing anything; note that `params` are not
+/// applied in that case. Requires `sudo` permissions.
+pub fn modprobe(
+    shell: &impl Execute,
+    module: &str,
+    params: &[&str],
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let mut args = escape_for_bash(module);
+    for param in params {
+        args.push(' ');
+        args.push_str(&escape_for_bash(param));
+    }
+
+    if module.ends_with(".ko") {
+        // Unlike `modprobe`, `insmod` fails if the module is already loaded.
+        let name = std::path::Path::new(module)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if lsmod_contains(shell, &name, dry_run)? {
+            return Ok(());
+        }
+
+        shell.run(cmd!("sudo insmod {}", args).dry_run(dry_run))?;
+    } else {
+        shell.run(cmd!("sudo modprobe {}", args).dry_run(dry_run))?;
+    }
+
+    Ok(())
+}
+
+/// Unload the kernel module `module`. Requires `sudo` permissions.
+pub fn rmmod(shell: &impl Execute, module: &str, dry_run: bool) -> Result<(), SshError> {
+    shell.run(cmd!("sudo rmmod {}", escape_for_bash(module)).dry_run(dry_run))?;
+
+    Ok(())
+}
+
+/// Returns `true` if the kernel module `module` is loaded, according to `lsmod`. Dashes and
+/// underscores in module names are interchangeable. In dry run mode, this always returns `false`.
+pub fn lsmod_contains(shell: &impl Execute, module: &str, dry_run: bool) -> Result<bool, SshError> {
+    let lsmod = shell.run(cmd!("lsmod").dry_run(dry_run))?.stdout;
+    let module = module.replace('-', "_");
+
+    // The first line is a header. Each other line starts with a module name.
+    Ok(lsmod
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .any(|name| name == module))
+}
+
+/// Start `cmd` in the background on the remote, such that it keeps running after we disconnect,
+/// and return its PID right away. Its stdout and stderr are written to `logfile` on the remote.
+/// The PID can be used with e.g. `kill`, or the process can be found with `pgrep`. In dry run mode,
+/// this returns 0.
+///
+/// Unlike `SshShell::spawn`, the output of `cmd` is not returned and its completion cannot be
+/// waited for. The command runs without a pty, so it cannot use `sudo` with a password.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let pid = spawn_detached(&shell, cmd!("./memcached -p 11211").cwd("memcached"), "mc.log", false)?;
+/// ```
+pub fn spawn_detached(
+    shell: &impl Execute,
+    cmd: SshCommand,
+    logfile: &str,
+    dry_run: bool,
+) -> Result<u32, SshError> {
+    // Redirecting all of the command's I/O lets the channel close without waiting for it.
+    let cmd = cmd!(
+        "nohup bash -c {} > {} 2>&1 < /dev/null & echo $!",
+        escape_for_bash(&cmd.remote_cmd()),
+        escape_for_bash(logfile)
+    );
+    let raw = cmd.cmd().to_owned();
+    let out = shell.run(cmd.no_pty().dry_run(dry_run))?.stdout;
+
+    if dry_run {
+        return Ok(0);
+    }
+
+    out.trim().parse().map_err(|_| SshError::UnexpectedOutput {
+        cmd: raw,
+        output: out.clone(),
+    })
+}
+
+/// Run `check` every `interval` until it exits successfully. If it still fails after `timeout`,
+/// `SshError::Timeout` is returned with the output of the last attempt. `check` is run with
+/// `allow_error`, so failed attempts are not errors. In dry run mode, `check` is run once.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// // Wait for the server to start listening.
+/// wait_until(&shell, cmd!("nc -z localhost 8080"), Duration::from_secs(1), Duration::from_secs(60), false)?;
+/// ```
+pub fn wait_until(
+    shell: &impl Execute,
+    check: SshCommand,
+    interval: Duration,
+    timeout: Duration,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let start = Instant::now();
+    let check = check.allow_error().dry_run(dry_run);
+
+    loop {
+        let stdout = match shell.run(check.clone()) {
+            Ok(out) if out.exit_status == 0 => return Ok(()),
+            Ok(out) => out.stdout,
+            Err(SshError::NonZeroExit { .. }) => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        if start.elapsed() >= timeout {
+            return Err(SshError::Timeout {
+                cmd: check.cmd().to_owned(),
+                after: timeout,
+                stdout,
+            });
+        }
+
+        debug!("Condition not met yet: {}", check.cmd());
+        std::thread::sleep(interval);
+    }
+}
+
+/// The exit code of `pgrep` and `pkill` when no processes match.
+const PGREP_NO_MATCH: i32 = 1;
+
+/// Returns the PIDs of the processes whose name matches the regex `pattern`, according to `pgrep`.
+/// If no processes match, this returns an empty `Vec`. In dry run mode, this always returns an
+/// empty `Vec`.
+pub fn pgrep(shell: &impl Execute, pattern: &str, dry_run: bool) -> Result<Vec<u32>, SshError> {
+    let cmd = cmd!("pgrep {}", escape_for_bash(pattern));
+    let raw = cmd.cmd().to_owned();
+    let out = match shell.run(cmd.dry_run(dry_run)) {
+        Ok(output) => output.stdout,
+        Err(SshError::NonZeroExit {
+            exit: PGREP_NO_MATCH,
+            ..
+        }) => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    out.split_whitespace()
+        .map(|pid| pid.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| SshError::UnexpectedOutput {
+            cmd: raw,
+            output: out.clone(),
+        })
+}
+
+/// Send `signal` (e.g. 9 for `SIGKILL`; `SIGTERM` by default) to all processes whose name matches
+/// the regex `pattern`, using `pkill`. It is not an error if no processes match.
+pub fn pkill(
+    shell: &impl Execute,
+    pattern: &str,
+    signal: Option<i32>,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let signal = signal.map(|sig| format!(" -{}", sig)).unwrap_or_default();
+
+    match shell.run(cmd!("pkill{} {}", signal, escape_for_bash(pattern)).dry_run(dry_run)) {
+        Ok(_)
+        | Err(SshError::NonZeroExit {
+            exit: PGREP_NO_MATCH,
+            ..
+        }) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns `true` if `path` is a mountpoint. In dry run mode, this always returns `true`.
+pub fn is_mountpoint(shell: &impl Execute, path: &str, dry_run: bool) -> Result<bool, SshError> {
+    match shell.run(cmd!("mountpoint -q {}", escape_for_bash(path)).dry_run(dry_run)) {
+        Ok(_) => Ok(true),
+        Err(SshError::NonZeroExit { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns `true` if `path` exists on the remote (`test -e`). In dry run mode, this always returns
+/// `true`.
+pub fn path_exists(shell: &impl Execute, path: &str, dry_run: bool) -> Result<bool, SshError> {
+    test_path(shell, "-e", path, dry_run)
+}
+
+/// Returns `true` if `path` exists on the remote and is a regular file (`test -f`). In dry run
+/// mode, this always returns `true`.
+pub fn is_file(shell: &impl Execute, path: &str, dry_run: bool) -> Result<bool, SshError> {
+    test_path(shell, "-f", path, dry_run)
+}
+
+/// Returns `true` if `path` exists on the remote and is a directory (`test -d`). In dry run mode,
+/// this always returns `true`.
+pub fn is_dir(shell: &impl Execute, path: &str, dry_run: bool) -> Result<bool, SshError> {
+    test_path(shell, "-d", path, dry_run)
+}
+
+/// Returns the contents of the remote text file `path`, with trailing whitespace removed. If the
+/// file does not exist, `cat` fails and a `NonZeroExit` error whose `stderr` explains the failure
+/// is returned. In dry run mode, this returns an empty string.
+///
+/// This is meant for small text files, such as configs or results. To copy binary or large files,
+/// use `SshShell::download` instead, which transfers the file via SFTP.
+pub fn read_file(shell: &impl Execute, path: &str, dry_run: bool) -> Result<String, SshError> {
+    let contents = shell
+        .run(
+            cmd!("cat {}", escape_for_bash(path))
+                .no_pty()
+                .dry_run(dry_run),
+        )?
+        .stdout;
+
+    Ok(contents.trim_end().to_owned())
+}
+
+/// Write `contents` to the remote file `path`, replacing the file if it exists. The contents are
+/// passed to `tee` on stdin, so they do not need to be escaped. If `sudo` is set, `tee` runs as
+/// root, e.g. to write to `/etc`. Since commands with stdin have no pty, this requires
+/// passwordless `sudo`.
+///
+/// This is the counterpart of `read_file`. To copy binary or large files, use `SshShell::upload`
+/// instead.
+pub fn write_file(
+    shell: &impl Execute,
+    path: &str,
+    contents: &str,
+    sudo: bool,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let tee = if sudo { "sudo tee" } else { "tee" };
+    shell.run(
+        cmd!("{} {} > /dev/null", tee, escape_for_bash(path))
+            .stdin(contents)
+            .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// Append `line` to the remote file `path`, unless the file already contains exactly that line,
+/// e.g. to add a module to `/etc/modules` or an `export` to `.bashrc`. The file is created if it
+/// does not exist. `line` is escaped, so it may contain quotes, `$`, etc. If `sudo` is set, the
+/// file is read and written as root.
+pub fn ensure_line_in_file(
+    shell: &impl Execute,
+    line: &str,
+    path: &str,
+    sudo: bool,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let sudo = if sudo { "sudo " } else { "" };
+    let line = escape_for_bash(line);
+    let path = escape_for_bash(path);
+
+    // `grep` also fails if the file does not exist yet.
+    match shell.run(cmd!("{}grep -qxF -- {} {}", sudo, line, path).dry_run(dry_run)) {
+        Ok(_) if !dry_run => return Ok(()),
+        Ok(_) | Err(SshError::NonZeroExit { .. }) => {}
+        Err(e) => return Err(e),
+    }
+
+    shell.run(
+        cmd!(
+            "printf '%s\\n' {} | {}tee -a {} > /dev/null",
+            line,
+            sudo,
+            path
+        )
+        .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// Runs `test <flag> <path>`, returning whether it succeeded.
+fn test_path(
+    shell: &impl Execute,
+    flag: &str,
+    path: &str,
+    dry_run: bool,
+) -> Result<bool, SshError> {
+    match shell.run(cmd!("test {} {}", flag, escape_for_bash(path)).dry_run(dry_run)) {
+        Ok(_) => Ok(true),
+        Err(SshError::NonZeroExit { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Bind mount `source` at `target` if there is not already something mounted at `target`.
+/// Requires `sudo` permissions.
+pub fn ensure_bind_mount(
+    shell: &impl Execute,
+    source: &str,
+    target: &str,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    if dry_run || !is_mountpoint(shell, target, dry_run)? {
+        shell.run(bind_mount(source, target).dry_run(dry_run))?;
+    }
+
+    Ok(())
+}
+
+/// Clone the git repository `repo` into `dest`, optionally checking out `branch` and doing a
+/// shallow clone of the given `depth`. If `dest` is already a git repository, we `git pull`
+/// instead, so this is safe to call repeatedly.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// git_clone(shell, "https://github.com/mark-i-m/spurs", "spurs", Some("master"), Some(1), false)?;
+/// ```
+pub fn git_clone(
+    shell: &impl Execute,
+    repo: &str,
+    dest: &str,
+    branch: Option<&str>,
+    depth: Option<u32>,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let exists = !dry_run
+        && match shell.run(cmd!("test -d {}/.git", escape_for_bash(dest))) {
+            Ok(_) => true,
+            Err(SshError::NonZeroExit { .. }) => false,
+            Err(e) => return Err(e),
+        };
+
+    if exists {
+        shell.run(cmd!("git -C {} pull", escape_for_bash(dest)).dry_run(dry_run))?;
+    } else {
+        let mut clone = String::from("git clone");
+        if let Some(branch) = branch {
+            clone.push_str(&format!(" --branch {}", escape_for_bash(branch)));
+        }
+        if let Some(depth) = depth {
+            clone.push_str(&format!(" --depth {}", depth));
+        }
+        shell.run(
+            cmd!(
+                "{} {} {}",
+                clone,
+                escape_for_bash(repo),
+                escape_for_bash(dest)
+            )
+            .dry_run(dry_run),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Download `url` to `dest` on the remote using `curl`, or `wget` if `curl` is not installed. If
+/// `sha256` is given, the SHA-256 checksum of the downloaded file is compared against it, and
+/// `SshError::ChecksumMismatch` is returned if they differ.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// download(
+///     shell,
+///     "https://cdn.kernel.org/pub/linux/kernel/v5.x/linux-5.4.tar.xz",
+///     "linux-5.4.tar.xz",
+///     Some("bf338980b1670bca287f9994b7441c2361907635879169c64ae78364efc5f491"),
+///     false,
+/// )?;
+/// ```
+pub fn download(
+    shell: &impl Execute,
+    url: &str,
+    dest: &str,
+    sha256: Option<&str>,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let escaped_dest = escape_for_bash(dest);
+    shell.run(
+        cmd!(
+            "if command -v curl >/dev/null; then curl -fL -o {dest} {url}; \
+             else wget -O {dest} {url}; fi",
+            dest = escaped_dest,
+            url = escape_for_bash(url),
+        )
+        .use_bash()
+        .dry_run(dry_run),
+    )?;
+
+    if let Some(expected) = sha256 {
+        let raw = format!("sha256sum {}", escaped_dest);
+        let out = shell.run(cmd!("{}", raw).dry_run(dry_run))?.stdout;
+
+        if dry_run {
+            return Ok(());
+        }
+
+        let actual = match out.split_whitespace().next() {
+            Some(actual) => actual,
+            None => {
+                return Err(SshError::UnexpectedOutput {
+                    cmd: raw,
+                    output: out,
+                })
+            }
+        };
+
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(SshError::ChecksumMismatch {
+                file: dest.to_owned(),
+                expected: expected.to_owned(),
+                actual: actual.to_owned(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Extract the tarball `archive` into the directory `into`, creating it if needed. Compression is
+/// detected from the extension of `archive` (`.gz`, `.xz`, or `.bz2`).
+pub fn tar_extract(
+    shell: &impl Execute,
+    archive: &str,
+    into: &str,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let into = escape_for_bash(into);
+
+    shell.run(cmd!("mkdir -p {}", into).dry_run(dry_run))?;
+    shell.run(
+        cmd!(
+            "tar x{}f {} -C {}",
+            tar_compression_flag(archive),
+            escape_for_bash(archive),
+            into
+        )
+        .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// Create the tarball `archive` containing `files`. Compression is chosen based on the extension
+/// of `archive` (`.gz`, `.xz`, or `.bz2`).
+pub fn tar_create(
+    shell: &impl Execute,
+    archive: &str,
+    files: &[&str],
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let files = files
+        .iter()
+        .map(|f| escape_for_bash(f))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    shell.run(
+        cmd!(
+            "tar c{}f {} {}",
+            tar_compression_flag(archive),
+            escape_for_bash(archive),
+            files
+        )
+        .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// The `tar` flag for the compression implied by the extension of `archive`, if any.
+fn tar_compression_flag(archive: &str) -> &'static str {
+    if archive.ends_with(".gz") || archive.ends_with(".tgz") {
+        "z"
+    } else if archive.ends_with(".xz") {
+        "J"
+    } else if archive.ends_with(".bz2") {
+        "j"
+    } else {
+        ""
+    }
+}
+
+/// Run `sudo systemctl <action> <service>` and return its stdout.
+///
+/// `systemctl status` exits with a non-zero code if the service is not running, so for
+/// `SystemctlAction::Status` this is not treated as an error. Instead, callers can parse the
+/// output to see if the service is active.
+pub fn systemctl(
+    shell: &impl Execute,
+    action: SystemctlAction,
+    service: &str,
+    dry_run: bool,
+) -> Result<String, SshError> {
+    let action_str = match action {
+        SystemctlAction::Start => "start",
+        SystemctlAction::Stop => "stop",
+        SystemctlAction::Restart => "restart",
+        SystemctlAction::Enable => "enable",
+        SystemctlAction::Disable => "disable",
+        SystemctlAction::Status => "status",
+    };
+
+    let cmd = cmd!("sudo systemctl {} {}", action_str, escape_for_bash(service));
+    let cmd = if action == SystemctlAction::Status {
+        cmd.allow_error()
+    } else {
+        cmd
+    };
+
+    Ok(shell.run(cmd.dry_run(dry_run))?.stdout)
+}
+
+/// Add `params` to the kernel command line (`GRUB_CMDLINE_LINUX` in `/etc/default/grub`) and
+/// regenerate the GRUB config with `update-grub` or `grub2-mkconfig`, whichever is available.
+/// Parameters that are already present are not duplicated, and parameters of the form
+/// `key=value` replace any existing value for `key`, unless `key` is given more than once (e.g.
+/// several `console=`), in which case all of them are kept. Requires `sudo`.
+///
+/// The new command line only takes effect after a reboot (e.g. with `reboot`).
+///
+/// # Example
+///
+/// ```rust,ignore
+/// set_kernel_cmdline(shell, &["transparent_hugepage=never", "isolcpus=2-3"], false)?;
+/// reboot(shell, false)?;
+/// ```
+pub fn set_kernel_cmdline(
+    shell: &impl Execute,
+    params: &[&str],
+    dry_run: bool,
+) -> Result<(), SshError> {
+    const GRUB_CMDLINE: &str = "GRUB_CMDLINE_LINUX=";
+
+    let raw = format!("grep '^{}' /etc/default/grub", GRUB_CMDLINE);
+    let out = shell.run(cmd!("{}", raw).dry_run(dry_run))?.stdout;
+    let current = match out.trim().strip_prefix(GRUB_CMDLINE) {
+        Some(current) => current.trim_matches(|c| c == '"' || c == '\''),
+        None if dry_run => "",
+        None => {
+            return Err(SshError::UnexpectedOutput {
+                cmd: raw,
+                output: out,
+            })
+        }
+    };
+
+    // Escape the characters that are special in the replacement part of a sed `s|...|...|`.
+    let new = merge_kernel_cmdline(current, params)
+        .replace('\\', "\\\\")
+        .replace('&', "\\&")
+        .replace('|', "\\|");
+    let sed = format!("s|^{0}.*|{0}\"{1}\"|", GRUB_CMDLINE, new);
+    shell.run(cmd!("sudo sed -i {} /etc/default/grub", escape_for_bash(&sed)).dry_run(dry_run))?;
+
+    shell.run(
+        cmd!(
+            "if command -v update-grub >/dev/null; then sudo update-grub; \
+             else sudo grub2-mkconfig -o /boot/grub2/grub.cfg; fi"
+        )
+        .use_bash()
+        .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// Add `params` to the space-separated kernel command line `current`, skipping parameters that are
+/// already present. A `key=value` parameter replaces the existing value of `key`, unless `key` is
+/// repeated in `current` or in `params` (e.g. several `console=`, or `hugepagesz=` and `hugepages=`
+/// pairs for each page size), in which case it is added as is.
+fn merge_kernel_cmdline(current: &str, params: &[&str]) -> String {
+    let key = |param: &str| param.split('=').next().unwrap_or("").to_owned();
+    let count = |params: &[&str], k: &str| params.iter().filter(|p| key(p) == k).count();
+
+    let current: Vec<&str> = current.split_whitespace().collect();
+    let mut merged: Vec<String> = current.iter().map(|&p| p.to_owned()).collect();
+    for &param in params {
+        if merged.iter().any(|p| p == param) {
+            continue;
+        }
+
+        let k = key(param);
+        let repeated = count(&current, &k) > 1 || count(params, &k) > 1;
+        match merged.iter_mut().find(|p| key(p) == k) {
+            Some(existing) if !repeated => *existing = param.to_owned(),
+            _ => merged.push(param.to_owned()),
+        }
+    }
+
+    merged.join(" ")
+}
+
+/// Checks whether `cmd` is idempotent. We run `cmd`, run `probe` to take a snapshot of the state
+/// we care about, then run `cmd` again and take another snapshot. Returns `true` if the output of
+/// `probe` was the same both times (i.e. the second run didn't change anything).
+///
+/// This is mostly useful for testing setup routines.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// // Does this leave us with a single entry in /etc/fstab?
+/// assert_idempotent(shell, add_fstab_entry_cmd, cmd!("cat /etc/fstab"), false)?;
+/// ```
+pub fn assert_idempotent(
+    shell: &impl Execute,
+    cmd: SshCommand,
+    probe: SshCommand,
+    dry_run: bool,
+) -> Result<bool, SshError> {
+    shell.run(cmd.clone().dry_run(dry_run))?;
+    let before = shell.run(probe.clone().dry_run(dry_run))?.stdout;
+
+    shell.run(cmd.dry_run(dry_run))?;
+    let after = shell.run(probe.dry_run(dry_run))?.stdout;
+
+    Ok(before == after)
+}
+
+/// Detect the remote machine's distro from the `ID` and `ID_LIKE` fields of `/etc/os-release`.
+/// Derivatives of the known distros (e.g. Linux Mint or Rocky Linux) are detected as the distro
+/// they are based on. In dry run mode, this returns `Distro::Other` with an empty `ID`.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// match detect_distro(&shell, false)? {
+///     Distro::Ubuntu | Distro::Debian => shell.run(ubuntu::apt_install(&["gcc"]))?,
+///     Distro::Centos | Distro::Fedora => shell.run(centos::yum_install(&["gcc"]))?,
+///     other => panic!("unsupported distro: {:?}", other),
+/// };
+/// ```
+pub fn detect_distro(shell: &impl Execute, dry_run: bool) -> Result<Distro, SshError> {
+    let os_release = read_file(shell, "/etc/os-release", dry_run)?;
+    Ok(parse_os_release(&os_release))
+}
+
+/// Install the given packages with the package manager of the remote's distro (see
+/// `detect_distro`): `apt-get` on Debian and Ubuntu (after updating the package lists), `yum` on
+/// CentOS, `dnf` on Fedora, `pacman` on Arch, and `apk` on Alpine. Requires `sudo` privileges.
+///
+/// Returns an `SshError::IoError` of kind `Unsupported` for other distros. In dry run mode, the
+/// distro cannot be detected, so no install command is run.
+pub fn install(shell: &impl Execute, pkgs: &[&str], dry_run: bool) -> Result<(), SshError> {
+    let distro = detect_distro(shell, dry_run)?;
+    install_on(shell, distro, pkgs, dry_run)
+}
+
+/// Install the given packages with the package manager of `distro`. See `install`.
+fn install_on(
+    shell: &impl Execute,
+    distro: Distro,
+    pkgs: &[&str],
+    dry_run: bool,
+) -> Result<(), SshError> {
+    match distro {
+        Distro::Ubuntu | Distro::Debian => {
+            shell.run(ubuntu::apt_update().dry_run(dry_run))?;
+            shell.run(ubuntu::apt_install(pkgs).dry_run(dry_run))?;
+        }
+        Distro::Centos => {
+            shell.run(centos::yum_install(pkgs).dry_run(dry_run))?;
+        }
+        Distro::Fedora => {
+            shell.run(centos::dnf_install(pkgs).dry_run(dry_run))?;
+        }
+        Distro::Arch => {
+            shell.run(arch::pacman_install(pkgs).dry_run(dry_run))?;
+        }
+        Distro::Alpine => {
+            shell.run(alpine::apk_add(pkgs).dry_run(dry_run))?;
+        }
+        Distro::Other(_) if dry_run => {}
+        Distro::Other(id) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("no package manager known for distro: {:?}", id),
+            )
+            .into())
+        }
+    }
+
+    Ok(())
+}
+
+/// Install the given packages with `install`, then check that each of `binaries` is on the
+/// `PATH`, e.g. because a package name differs from the name of its binary. If a binary is
+/// missing, the `SshError::NonZeroExit` of `command -v <binary>` is returned. Requires `sudo`
+/// privileges.
+///
+/// ```rust,ignore
+/// ensure_installed(&shell, &["build-essential", "linux-tools-generic"], &["gcc", "perf"], false)?;
+/// ```
+pub fn ensure_installed(
+    shell: &impl Execute,
+    packages: &[&str],
+    binaries: &[&str],
+    dry_run: bool,
+) -> Result<(), SshError> {
+    install(shell, packages, dry_run)?;
+
+    for binary in binaries {
+        shell.run(cmd!("command -v {}", escape_for_bash(binary)).dry_run(dry_run))?;
+    }
+
+    Ok(())
+}
+
+/// Parses the contents of `/etc/os-release`. See `detect_distro`.
+fn parse_os_release(os_release: &str) -> Distro {
+    let field = |name: &str| {
+        os_release
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix(name)?.strip_prefix('='))
+            .map(|value| value.trim_matches(|c| c == '"' || c == '\'').to_owned())
+            .next()
+            .unwrap_or_default()
+    };
+    let id = field("ID");
+    let id_like = field("ID_LIKE");
+
+    let known = |id: &str| match id {
+        "ubuntu" => Some(Distro::Ubuntu),
+        "debian" => Some(Distro::Debian),
+        "centos" | "rhel" | "rocky" | "almalinux" => Some(Distro::Centos),
+        "fedora" => Some(Distro::Fedora),
+        "arch" => Some(Distro::Arch),
+        "alpine" => Some(Distro::Alpine),
+        _ => None,
+    };
+
+    std::iter::once(id.as_str())
+        .chain(id_like.split_whitespace())
+        .find_map(known)
+        .unwrap_or(Distro::Other(id))
+}
+
+/// Collects information about the remote machine's distro, kernel, CPU, memory, and block
+/// devices. If any of the probes fails, the corresponding field is `None`, rather than failing the
+/// whole fingerprint.
+///
+/// With the `serde` feature, `MachineInfo` is serializable, so the result can be saved alongside
+/// experimental results.
+pub fn machine_fingerprint(shell: &impl Execute, dry_run: bool) -> Result<MachineInfo, SshError> {
+    let probe = |cmd: SshCommand| match shell.run(cmd.dry_run(dry_run)) {
+        Ok(out) => Some(out.stdout),
+        Err(e) => {
+            warn!("Unable to probe machine: {}", e);
+            None
+        }
+    };
+
+    Ok(MachineInfo {
+        os_release: probe(cmd!("cat /etc/os-release")),
+        kernel: probe(cmd!("uname -a")),
+        cpu: probe(cmd!("lscpu")),
+        memory: probe(cmd!("cat /proc/meminfo")),
+        block_devices: probe(cmd!("lsblk -J")),
+    })
+}
+
+/// Measure the network bandwidth (in bits/second) from `client` to `server` with `iperf3`, which
+/// must be installed on both. An `iperf3` server is spawned on `server` for the duration of the
+/// measurement, and the client sends data to it for `duration_secs` seconds. In dry run mode, this
+/// returns 0.
+///
+/// The client connects to the address that `server` was reached at (i.e. the one used by the SSH
+/// connection), so both machines should be on the same network.
+///
+/// Requires the `serde_json` feature, to parse the JSON output of `iperf3`.
+#[cfg(feature = "serde_json")]
+pub fn measure_bandwidth(
+    server: &SshShell,
+    client: &SshShell,
+    duration_secs: u32,
+    dry_run: bool,
+) -> Result<f64, SshError> {
+    // `SSH_CONNECTION` looks like `<client ip> <client port> <server ip> <server port>`.
+    let cmd = cmd!("echo $SSH_CONNECTION");
+    let raw = cmd.cmd().to_owned();
+    let conn = server.run(cmd.dry_run(dry_run))?.stdout;
+    let server_ip = match conn.split_whitespace().nth(2) {
+        Some(ip) => ip.to_owned(),
+        None if dry_run => "<server ip>".into(),
+        None => {
+            return Err(SshError::UnexpectedOutput {
+                cmd: raw,
+                output: conn,
+            })
+        }
+    };
+
+    // The server exits after one measurement (`-1`).
+    let handle = server.spawn(cmd!("iperf3 -s -1").dry_run(dry_run))?;
+
+    // Give the server a moment to start listening.
+    if !dry_run {
+        std::thread::sleep(Duration::from_secs(1));
+    }
+
+    let cmd = cmd!("iperf3 -c {} -t {} -J", server_ip, duration_secs);
+    let raw = cmd.cmd().to_owned();
+    let result = client.run(cmd.dry_run(dry_run));
+
+    // If the measurement failed, the server is still waiting for a client. The brackets keep
+    // `pkill` from matching (and killing) the shell running it.
+    if result.is_err() {
+        server.run(cmd!("pkill -f 'iperf3 -s -[1]'").allow_error())?;
+    }
+    handle.join().1?;
+
+    let json = result?.stdout;
+    if dry_run {
+        return Ok(0.0);
+    }
+
+    parse_iperf_bandwidth(&json).ok_or(SshError::UnexpectedOutput {
+        cmd: raw,
+        output: json,
+    })
+}
+
+/// Copy the local directory `local_dir` to `remote_dir` on the remote of `shell` with `rsync -a`,
+/// which must be installed locally and on the remote. If `delete` is set, files in `remote_dir`
+/// that don't exist in `local_dir` are deleted. As usual for `rsync`, a trailing `/` on
+/// `local_dir` copies its contents rather than the directory itself.
+///
+/// `rsync` runs locally and connects to the remote using the local `ssh` command (see
+/// `SshShell::ssh_command`).
+///
+/// # Example
+///
+/// ```rust,ignore
+/// rsync_to(&shell, "results/", "/data/results", false, false)?;
+/// ```
+pub fn rsync_to(
+    shell: &SshShell,
+    local_dir: &str,
+    remote_dir: &str,
+    delete: bool,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let remote_dir = rsync_remote_path(&shell.ssh_destination(), remote_dir);
+    rsync(
+        &rsync_args(&shell.ssh_command(), local_dir, &remote_dir, delete),
+        dry_run,
+    )
+}
+
+/// Like `rsync_to`, but copies `remote_dir` on the remote of `shell` to the local directory
+/// `local_dir`.
+pub fn rsync_from(
+    shell: &SshShell,
+    remote_dir: &str,
+    local_dir: &str,
+    delete: bool,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let remote_dir = rsync_remote_path(&shell.ssh_destination(), remote_dir);
+    rsync(
+        &rsync_args(&shell.ssh_command(), &remote_dir, local_dir, delete),
+        dry_run,
+    )
+}
+
+/// Returns the `rsync` path (`user@host:path`) of `path` on the `ssh` `destination` (`user@host`).
+fn rsync_remote_path(destination: &str, path: &str) -> String {
+    match destination.split_once('@') {
+        // IPv6 addresses need brackets.
+        Some((user, host)) if host.contains(':') => format!("{}@[{}]:{}", user, host, path),
+        _ => format!("{}:{}", destination, path),
+    }
+}
+
+/// Returns the arguments to `rsync` to copy `src` to `dst` using the given `ssh` command.
+fn rsync_args(ssh: &str, src: &str, dst: &str, delete: bool) -> Vec<String> {
+    let mut args = vec!["-a".to_owned()];
+    if delete {
+        args.push("--delete".into());
+    }
+    args.extend(vec!["-e".into(), ssh.into(), src.into(), dst.into()]);
+    args
+}
+
+/// Runs `rsync` locally with the given arguments.
+fn rsync(args: &[String], dry_run: bool) -> Result<(), SshError> {
+    let escaped: Vec<_> = args.iter().map(|arg| escape_for_bash(arg)).collect();
+    let cmd = format!("rsync {}", escaped.join(" "));
+
+    // Echo the command the same way `SshShell` does, but as a local one.
+    println!(
+        "{:-<80}\n{}\n{}",
+        "",
+        console::style("localhost").blue(),
+        console::style(&cmd).yellow().bold()
+    );
+
+    if dry_run {
+        return Ok(());
+    }
+
+    let status = std::process::Command::new("rsync").args(args).status()?;
+    if !status.success() {
+        return Err(SshError::NonZeroExit {
+            cmd,
+            exit: status.code().unwrap_or(-1),
+            stdout: String::new(),
+            stderr: String::new(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns the bandwidth (in bits/second) received by the server from the JSON output of an
+/// `iperf3` client (`iperf3 -c <server> -J`).
+#[cfg(feature = "serde_json")]
+fn parse_iperf_bandwidth(json: &str) -> Option<f64> {
+    let json: serde_json::Value = serde_json::from_str(json).ok()?;
+    json["end"]["sum_received"]["bits_per_second"].as_f64()
+}
+
+/// Estimate the clock skew of each of `others` relative to `reference`, in seconds. A positive
+/// skew means that the machine's clock is ahead of the reference's clock.
+///
+/// Each machine's clock is compared to the local clock by reading the remote time with `date` and
+/// assuming that it was read halfway through the round trip, as NTP does. Thus, the error of each
+/// estimate is at most half of the round trip times to the machine and to the reference.
+///
+/// The skews are signed seconds rather than `Duration`s, because a `Duration` cannot be negative.
+/// The machines are read one after another rather than simultaneously. Comparing each one to the
+/// local clock keeps that from skewing the estimates, as long as the local clock doesn't drift
+/// noticeably during the measurement.
+pub fn clock_skew<E: Execute>(
+    reference: &impl Execute,
+    others: &[E],
+) -> Result<Vec<f64>, SshError> {
+    let reference = clock_offset(reference)?;
+    others
+        .iter()
+        .map(|shell| Ok(clock_offset(shell)? - reference))
+        .collect()
+}
+
+/// Returns the offset of the remote clock from the local clock, in seconds. See `clock_skew`.
+fn clock_offset(shell: &impl Execute) -> Result<f64, SshError> {
+    let now = || {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("local clock is before 1970")
+            .as_secs_f64()
+    };
+
+    let cmd = cmd!("date +%s.%N").quiet();
+    let raw = cmd.cmd().to_owned();
+
+    let before = now();
+    let remote = shell.run(cmd)?.stdout;
+    let after = now();
+
+    let remote: f64 = remote
+        .trim()
+        .parse()
+        .map_err(|_| SshError::UnexpectedOutput {
+            cmd: raw,
+            output: remote.clone(),
+        })?;
+
+    Ok(remote - (before + after) / 2.0)
+}
+
+/// Step the remote clock to the correct time right away, using `chronyc` if `chrony` is installed,
+/// or `ntpdate` otherwise. Requires `sudo` permissions.
+pub fn sync_time(shell: &impl Execute, dry_run: bool) -> Result<(), SshError> {
+    shell.run(
+        cmd!(
+            "if command -v chronyc >/dev/null; then sudo chronyc makestep; \
+             else sudo ntpdate pool.ntp.org; fi"
+        )
+        .use_bash()
+        .dry_run(dry_run),
+    )?;
+
+    Ok(())
+}
+
+/// The file containing the boot id, which is randomly generated on every boot.
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+
+/// How long `reboot` waits for the boot id to change after reconnecting.
+pub const REBOOT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How often `wait_for_reboot` checks the boot id.
+const REBOOT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Returns the boot id of the remote machine, which changes every time it boots. In dry run mode,
+/// this returns an empty string.
+pub fn get_boot_id(shell: &impl Execute, dry_run: bool) -> Result<String, SshError> {
+    read_file(shell, BOOT_ID, dry_run)
+}
+
+/// Wait until the remote machine has rebooted, i.e. its boot id is no longer `boot_id` (see
+/// `get_boot_id`), reconnecting as needed. Returns `SshError::NotRebooted` if the boot id is still
+/// the same after `timeout`.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let boot_id = get_boot_id(&shell, false)?;
+/// shell.run(cmd!("sudo kexec -e"))?;
+/// wait_for_reboot(&mut shell, &boot_id, Duration::from_secs(300), false)?;
+/// ```
+pub fn wait_for_reboot(
+    shell: &mut impl Execute,
+    boot_id: &str,
+    timeout: Duration,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let start = Instant::now();
+
+    loop {
+        let connected = match get_boot_id(shell, dry_run) {
+            Ok(id) if dry_run || id != boot_id => return Ok(()),
+            Ok(_) => {
+                debug!("Boot id unchanged. The machine has not rebooted yet.");
+                true
+            }
+            Err(e) => {
+                debug!("Unable to get boot id: {}", e);
+                false
+            }
+        };
+
+        if start.elapsed() >= timeout {
+            return Err(SshError::NotRebooted {
+                boot_id: boot_id.to_owned(),
+                after: timeout,
+            });
+        }
+
+        std::thread::sleep(REBOOT_POLL_INTERVAL);
+
+        // The connection is broken if the machine went down in the meantime.
+        if !connected {
+            if let Err(e) = shell.reconnect_with_limit(1) {
+                debug!("Unable to reconnect: {}", e);
+            }
+        }
+    }
+}
+
+/// Reboot and wait for the remote machine to come back up again. `wait` is how long to wait for
+/// the machine to go down before trying to reconnect; if we try to reconnect immediately, the
+/// machine will not have gone down yet. Requires `sudo`.
+///
+/// After reconnecting, we check that the machine's boot id changed, so that we know that it
+/// actually rebooted. If it did not change within `REBOOT_TIMEOUT`, `SshError::NotRebooted` is
+/// returned.
+///
+/// If `sudo reboot` is rejected (e.g. because of missing `sudo` permissions), the
+/// `SshError::NonZeroExit` error is returned. Other errors are expected, since the connection
+/// drops when the machine goes down.
+pub fn reboot(shell: &mut impl Execute, wait: Duration, dry_run: bool) -> Result<(), SshError> {
+    reboot_inner(shell, wait, None, dry_run)
+}
+
+/// Like `reboot`, but gives up with `SshError::ReconnectFailed` if the machine cannot be
+/// reconnected to within `max_attempts` attempts. Requires `sudo`.
+pub fn reboot_with_limit(
+    shell: &mut impl Execute,
+    wait: Duration,
+    max_attempts: usize,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    reboot_inner(shell, wait, Some(max_attempts), dry_run)
+}
+
+fn reboot_inner(
+    shell: &mut impl Execute,
+    wait: Duration,
+    max_attempts: Option<usize>,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    let boot_id = get_boot_id(shell, dry_run)?;
+
+    match shell.run(cmd!("sudo reboot").dry_run(dry_run)) {
+        // The command was rejected, so the machine is not going down.
+        Err(e @ SshError::NonZeroExit { .. }) => return Err(e),
+
+        // The connection dropped because the machine went down.
+        Err(e) => debug!("Connection dropped by reboot: {}", e),
+
+        Ok(_) => {}
+    }
+
+    if !dry_run {
+        std::thread::sleep(wait);
+
+        // Attempt to reconnect.
+        match max_attempts {
+            Some(max_attempts) => shell.reconnect_with_limit(max_attempts)?,
+            None => shell.reconnect()?,
+        }
+    }
+
+    wait_for_reboot(shell, &boot_id, REBOOT_TIMEOUT, dry_run)?;
+
+    // Make sure it worked.
+    shell.run(cmd!("whoami").dry_run(dry_run))?;
+
+    Ok(())
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// Tests
+///////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use log::info;
+
+    use spurs::{cmd, Execute, SshCommand, SshError, SshOutput};
+
+    /// An `Execute` implementation for use in tests.
+    #[derive(Clone, Debug)]
+    pub struct TestSshShell {
+        pub commands: std::sync::Arc<std::sync::Mutex<Vec<SshCommand>>>,
+
+        /// Commands containing any of these strings fail with a non-zero exit code.
+        pub fail_on: Vec<String>,
+
+        /// The exit code of failing commands.
+        pub fail_exit: i32,
+    }
+
+    impl TestSshShell {
+        pub fn new() -> Self {
+            // init logging if never done before...
+            use std::sync::Once;
+            static START: Once = Once::new();
+            START.call_once(|| {
+                env_logger::init();
+            });
+
+            Self {
+                commands: std::sync::Arc::new(std::sync::Mutex::new(vec![])),
+                fail_on: vec![],
+                fail_exit: 1,
+            }
+        }
+
+        /// Like `new`, but any command containing one of `patterns` fails.
+        pub fn failing_on(patterns: &[&str]) -> Self {
+            Self {
+                fail_on: patterns.iter().map(|&p| p.to_owned()).collect(),
+                ..Self::new()
+            }
+        }
+    }
+
+    impl Execute for TestSshShell {
+        fn run(&self, cmd: SshCommand) -> Result<SshOutput, SshError> {
+            info!("Test run({:#?})", cmd);
+
+            enum FakeCommand {
+                Blkid,
+                Lsblk,
+                CpuFreq,
+                Counter,
+                IoScheduler,
+                Df,
+                Lscpu,
+                Meminfo,
+                Governor,
+                Thp,
+                GrubCmdline,
+                Sha256sum,
+                BootId,
+                OsRelease,
+                NodeCpulist,
+                NodeMeminfo,
+                CpuOnline,
+                Hugepages,
+                Lsmod,
+                Date,
+                Pgrep,
+                Nohup,
+                Findmnt,
+                Unknown,
+            }
+
+            let short_cmd = {
+                if cmd.cmd().contains("blkid") {
+                    FakeCommand::Blkid
+                } else if cmd.cmd().starts_with("lsblk -J -o") {
+                    FakeCommand::Lsblk
+                } else if cmd.cmd().contains("scaling_cur_freq") {
+                    FakeCommand::CpuFreq
+                } else if cmd.cmd().contains("date +%s%N") {
+                    FakeCommand::Counter
+                } else if cmd.cmd().contains("queue/scheduler") {
+                    FakeCommand::IoScheduler
+                } else if cmd.cmd().contains("df -B1") {
+                    FakeCommand::Df
+                } else if cmd.cmd().contains("LC_ALL=C lscpu") {
+                    FakeCommand::Lscpu
+                } else if cmd.cmd().contains("/proc/meminfo") {
+                    FakeCommand::Meminfo
+                } else if cmd.cmd().contains("scaling_governor") {
+                    FakeCommand::Governor
+                } else if cmd
+                    .cmd()
+                    .contains("cat /sys/kernel/mm/transparent_hugepage")
+                {
+                    FakeCommand::Thp
+                } else if cmd.cmd().contains("grep '^GRUB_CMDLINE_LINUX='") {
+                    FakeCommand::GrubCmdline
+                } else if cmd.cmd().contains("sha256sum") {
+                    FakeCommand::Sha256sum
+                } else if cmd.cmd().contains("boot_id") {
+                    FakeCommand::BootId
+                } else if cmd.cmd().contains("/etc/os-release") {
+                    FakeCommand::OsRelease
+                } else if cmd.cmd().contains("node*/cpulist") {
+                    FakeCommand::NodeCpulist
+                } else if cmd.cmd().contains("node*/meminfo") {
+                    FakeCommand::NodeMeminfo
+                } else if cmd.cmd().contains("cpu/online") {
+                    FakeCommand::CpuOnline
+                } else if cmd.cmd().contains("cat /sys/kernel/mm/hugepages") {
+                    FakeCommand::Hugepages
+                } else if cmd.cmd() == "lsmod" {
+                    FakeCommand::Lsmod
+                } else if cmd.cmd().contains("date +%s.%N") {
+                    FakeCommand::Date
+                } else if cmd.cmd().starts_with("pgrep") {
+                    FakeCommand::Pgrep
+                } else if cmd.cmd().starts_with("nohup") {
+                    FakeCommand::Nohup
+                } else if cmd.cmd().starts_with("findmnt") {
+                    FakeCommand::Findmnt
+                } else {
+                    FakeCommand::Unknown
+                }
+            };
+
+            let fail = self.fail_on.iter().any(|p| cmd.cmd().contains(p.as_str()));
+            let raw_cmd = cmd.cmd().to_owned();
+
+            let num_commands = {
+                let mut commands = self.commands.lock().unwrap();
+                commands.push(cmd);
+                commands.len()
+            };
+
+            let stdout = match short_cmd {
+                FakeCommand::Blkid => "UUID=1fb958bf-de7e-428a-a0b7-a598f22e96fa\n".into(),
+                // `dm-0` is a logical volume on both `sdb` and `sdc`, so it is listed twice.
+                FakeCommand::Lsblk => match raw_cmd
+                    .trim_start_matches("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT")
+                {
+                    "" => r#"{
+                        "blockdevices": [
+                            {"kname":"foobar", "type":"disk", "size":"477G", "mountpoint":null,
+                                "children": [
+                                    {"kname":"foo", "type":"part", "size":"100G", "mountpoint":"/mnt/foo"},
+                                    {"kname":"bar", "type":"part", "size":"200G", "mountpoint":"/mnt/bar"},
+                                    {"kname":"baz", "type":"part", "size":"177G", "mountpoint":null}
+                                ]
+                            },
+                            {"kname":"sdb", "type":"disk", "size":"400G", "mountpoint":null,
+                                "children": [
+                                    {"kname":"dm-0", "type":"lvm", "size":"900G", "mountpoint":"/mnt/my data"}
+                                ]
+                            },
+                            {"kname":"sdc", "type":"disk", "size":"500G", "mountpoint":null,
+                                "children": [
+                                    {"kname":"dm-0", "type":"lvm", "size":"900G", "mountpoint":"/mnt/my data"}
+                                ]
+                            }
+                        ]
+                    }"#
+                    .into(),
+                    " /dev/foobar" => r#"{
+                        "blockdevices": [
+                            {"kname":"foobar", "type":"disk", "size":"477G", "mountpoint":null,
+                                "children": [
+                                    {"kname":"foo", "type":"part", "size":"100G", "mountpoint":"/mnt/foo"},
+                                    {"kname":"bar", "type":"part", "size":"200G", "mountpoint":"/mnt/bar"},
+                                    {"kname":"baz", "type":"part", "size":"177G", "mountpoint":null}
+                                ]
+                            }
+                        ]
+                    }"#
+                    .into(),
+                    " /dev/sda" => {
+                        r#"{"blockdevices": [{"kname":"sda", "type":"disk", "size":"477G"}]}"#.into()
+                    }
+                    " /dev/sdb" => {
+                        r#"{"blockdevices": [{"kname":"sdb", "type":"disk", "size":"400G"}]}"#.into()
+                    }
+                    " /dev/sdc" => {
+                        r#"{"blockdevices": [{"kname":"sdc", "type":"disk", "size":"500G"}]}"#.into()
+                    }
+                    _ => r#"{"blockdevices": []}"#.into(),
+                },
+                FakeCommand::CpuFreq => "2400000\n".into(),
+                FakeCommand::Counter => format!("{}\n", num_commands),
+                FakeCommand::IoScheduler => "mq-deadline kyber [bfq] none\n".into(),
+                FakeCommand::Df => "\
+                    Filesystem        1B-blocks        Used       Avail Use% Mounted on\n\
+                    /dev/sda2      270553174016 19648032768 83573497856  20% /\n\
+                    none                      0           0           0    - /proc/fake\n\
+                    /dev/sdb1         470974464   379809792    54689792  88% /mnt/my data\n"
+                    .into(),
+                FakeCommand::Lscpu => "\
+                    Architecture:        x86_64\n\
+                    CPU(s):              32\n\
+                    On-line CPU(s) list: 0-27,30\n\
+                    Off-line CPU(s) list: 28,29,31\n\
+                    Thread(s) per core:  2\n\
+                    Core(s) per socket:  8\n\
+                    Socket(s):           2\n\
+                    Model name:          Intel(R) Xeon(R) CPU E5-2630 v3 @ 2.40GHz\n"
+                    .into(),
+                FakeCommand::Meminfo => "\
+                    SwapFree:        1000000 kB\n\
+                    MemTotal:       16318340 kB\n\
+                    MemFree:         8675309 kB\n\
+                    HugePages_Total:       0\n\
+                    SwapTotal:       2097148 kB\n"
+                    .into(),
+                FakeCommand::Governor => "\
+                    /sys/devices/system/cpu/cpu0/cpufreq/scaling_governor:performance\n\
+                    /sys/devices/system/cpu/cpu10/cpufreq/scaling_governor:powersave\n\
+                    /sys/devices/system/cpu/cpu2/cpufreq/scaling_governor:performance\n"
+                    .into(),
+                FakeCommand::Thp => "always madvise [never]\n".into(),
+                FakeCommand::GrubCmdline => {
+                    "GRUB_CMDLINE_LINUX=\"quiet transparent_hugepage=always\"\n".into()
+                }
+                FakeCommand::Sha256sum => {
+                    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  foo.tar\n"
+                        .into()
+                }
+                // A new boot id every time, as if the machine rebooted in between.
+                FakeCommand::BootId => format!("{}\n", num_commands),
+                FakeCommand::OsRelease => "\
+                    NAME=\"Rocky Linux\"\n\
+                    VERSION=\"9.3 (Blue Onyx)\"\n\
+                    ID=\"rocky\"\n\
+                    ID_LIKE=\"rhel centos fedora\"\n\
+                    VERSION_ID=\"9.3\"\n"
+                    .into(),
+                // Node 2 is memory-only.
+                FakeCommand::NodeCpulist => "\
+                    /sys/devices/system/node/node0/cpulist:0-3,8-11\n\
+                    /sys/devices/system/node/node1/cpulist:4-7,12-15\n"
+                    .into(),
+                FakeCommand::NodeMeminfo => "\
+                    Node 0 MemTotal:       16318340 kB\n\
+                    Node 1 MemTotal:       16515072 kB\n\
+                    Node 2 MemTotal:       67108864 kB\n"
+                    .into(),
+                FakeCommand::CpuOnline => "0-3\n".into(),
+                FakeCommand::Hugepages => "512\n100\n".into(),
+                FakeCommand::Lsmod => "\
+                    Module                  Size  Used by\n\
+                    msr                    16384  0\n\
+                    kvm_intel             372736  0\n\
+                    kvm                  1032192  1 kvm_intel\n"
+                    .into(),
+                // Each command runs 10s "later" than the previous one.
+                FakeCommand::Date => format!("{}.5\n", 1_600_000_000 + 10 * num_commands),
+                FakeCommand::Pgrep => "1234\n5678\n".into(),
+                FakeCommand::Nohup => "4321\n".into(),
+                FakeCommand::Findmnt => "/mnt/foo\n/mnt/foo-bind\n".into(),
+                FakeCommand::Unknown => String::new(),
+            };
+
+            if fail {
+                info!("Failing command: {}", raw_cmd);
+
+                return Err(SshError::NonZeroExit {
+                    cmd: raw_cmd,
+                    exit: self.fail_exit,
+                    stdout,
+                    stderr: String::new(),
+                });
+            }
+
+            info!("Output: {}", stdout);
+
+            Ok(SshOutput {
+                stdout,
+                stderr: String::new(),
+                exit_status: 0,
+                truncated: false,
+                duration: Duration::ZERO,
+            })
+        }
+
+        fn duplicate(&self) -> Result<Self, SshError> {
+            Ok(self.clone())
+        }
+
+        fn reconnect(&mut self) -> Result<(), SshError> {
+            info!("Test reconnect");
+
+            Ok(())
+        }
+    }
+
+    macro_rules! expect_cmd_sequence {
+        ($shell:expr) => {
+            assert!($shell.commands.is_empty());
+        };
+        ($shell:expr, $($cmd:expr),+ $(,)?) => {
+            let expected: &[SshCommand] = &[$($cmd),+];
+            let locked = $shell.commands.lock().unwrap();
+
+            if locked.len() != expected.len() {
+                panic!("Number of commands run does not match expected number: \n Expected: {:#?}\nActual:  {:#?}====\n", expected, locked);
+            }
+
+            let mut fail = false;
+            let mut message = "Actual commands did not match expected commands: \n".to_owned();
+
+            for (expected, actual) in expected.iter().zip(locked.iter()) {
+                if expected != actual {
+                    fail = true;
+                    message.push_str(&format!("\nExpected: {:#?}\nActual:  {:#?}\n=====\n", expected, actual));
+                }
+            }
+
+            if fail {
+                panic!("{}", message);
+            }
+        };
+    }
+
+    #[test]
+    fn test_set_cpu_scaling_governor() {
+        assert_eq!(
+            super::set_cpu_scaling_governor("foobar"),
+            SshCommand::make_cmd(
+                "sudo cpupower frequency-set -g foobar",
+                None,
+                false,
+                false,
+                false,
+                false,
+            )
+        );
+    }
+
+    #[test]
+    fn test_set_cpu_freq() {
+        assert_eq!(
+            super::set_cpu_freq(Some(1_000_000), Some(2_000_000)),
+            SshCommand::make_cmd(
+                "sudo cpupower frequency-set -d 1000000 -u 2000000",
+                None,
+                false,
+                false,
+                false,
+                false,
+            )
+        );
+        assert_eq!(
+            super::set_cpu_freq(None, Some(2_000_000)),
+            SshCommand::make_cmd(
+                "sudo cpupower frequency-set -u 2000000",
+                None,
+                false,
+                false,
+                false,
+                false,
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_cpu_freq_none() {
+        super::set_cpu_freq(None, None);
+    }
+
+    #[test]
+    fn test_get_memory_info() {
+        let shell = TestSshShell::new();
+        let info = super::get_memory_info(&shell, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /proc/meminfo", None, false, false, false, false),
+        }
+        assert_eq!(
+            info,
+            super::MemoryInfo {
+                total_kb: 16318340,
+                free_kb: 8675309,
+                available_kb: None,
+                swap_total_kb: 2097148,
+                swap_free_kb: 1000000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_cpu_info() {
+        let shell = TestSshShell::new();
+        let info = super::get_cpu_info(&shell, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("LC_ALL=C lscpu", None, false, false, false, false),
+        }
+        assert_eq!(
+            info,
+            super::CpuInfo {
+                sockets: 2,
+                cores_per_socket: 8,
+                threads_per_core: 2,
+                online_cpus: 29,
+            }
+        );
+    }
+
+    #[test]
+    fn test_count_cpu_list() {
+        assert_eq!(super::count_cpu_list("0"), Some(1));
+        assert_eq!(super::count_cpu_list("0-3,8,10-11"), Some(7));
+        assert_eq!(super::count_cpu_list("3-1"), None);
+        assert_eq!(super::count_cpu_list(""), None);
+    }
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(super::parse_cpu_list("0"), Some(vec![0]));
+        assert_eq!(
+            super::parse_cpu_list("0-3,8,10-11"),
+            Some(vec![0, 1, 2, 3, 8, 10, 11])
+        );
+        assert_eq!(super::parse_cpu_list("3-1"), None);
+        assert_eq!(super::parse_cpu_list(""), None);
+    }
+
+    #[test]
+    fn test_get_numa_topology() {
+        use super::NumaNode;
+
+        let shell = TestSshShell::new();
+        assert_eq!(
+            super::get_numa_topology(&shell, false).unwrap(),
+            vec![
+                NumaNode {
+                    id: 0,
+                    cpus: vec![0, 1, 2, 3, 8, 9, 10, 11],
+                    memory_kb: 16318340,
+                },
+                NumaNode {
+                    id: 1,
+                    cpus: vec![4, 5, 6, 7, 12, 13, 14, 15],
+                    memory_kb: 16515072,
+                },
+                NumaNode {
+                    id: 2,
+                    cpus: vec![],
+                    memory_kb: 67108864,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_get_numa_topology_no_numa() {
+        let shell = TestSshShell::failing_on(&["/sys/devices/system/node"]);
+        assert_eq!(
+            super::get_numa_topology(&shell, false).unwrap(),
+            vec![super::NumaNode {
+                id: 0,
+                cpus: vec![0, 1, 2, 3],
+                memory_kb: 16318340,
+            }]
+        );
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd(
+                "grep -H . /sys/devices/system/node/node*/cpulist",
+                None, false, false, false, false
+            ),
+            SshCommand::make_cmd("cat /sys/devices/system/cpu/online", None, false, false, false, false),
+            SshCommand::make_cmd("cat /proc/meminfo", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_get_cpu_freq() {
+        let shell = TestSshShell::new();
+        let freq = super::get_cpu_freq(&shell, 3, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /sys/devices/system/cpu/cpu3/cpufreq/scaling_cur_freq", None, false, false, false, false),
+        }
+        assert_eq!(freq, 2_400_000);
+    }
+
+    #[test]
+    fn test_get_cpu_scaling_governor() {
+        let shell = TestSshShell::new();
+        let govs = super::get_cpu_scaling_governor(&shell, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("grep -H . /sys/devices/system/cpu/cpu*/cpufreq/scaling_governor", None, false, false, false, false),
+        }
+        assert_eq!(govs, vec!["performance", "performance", "powersave"]);
+    }
+
+    #[test]
+    fn test_set_io_scheduler() {
+        assert_eq!(
+            super::set_io_scheduler("/dev/sda", "kyber"),
+            SshCommand::make_cmd(
+                "echo kyber | sudo tee /sys/block/sda/queue/scheduler",
+                None,
+                true, // use_bash
+                false,
+                false,
+                false,
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_io_scheduler_unknown() {
+        super::set_io_scheduler("sda", "foobar");
+    }
+
+    #[test]
+    fn test_get_io_scheduler() {
+        let shell = TestSshShell::new();
+        let sched = super::get_io_scheduler(&shell, "sda", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /sys/block/sda/queue/scheduler", None, false, false, false, false),
+        }
+        assert_eq!(sched, "bfq");
+    }
+
+    #[test]
+    fn test_set_transparent_hugepages() {
+        let shell = TestSshShell::new();
+        super::set_transparent_hugepages(&shell, "madvise", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd(
+                "echo madvise | sudo tee /sys/kernel/mm/transparent_hugepage/enabled",
+                None, /* use_bash = */ true, false, false, false
+            ),
+        }
+    }
+
+    #[test]
+    fn test_modprobe() {
+        let shell = TestSshShell::new();
+        super::modprobe(&shell, "loop", &["max_loop=64"], false).unwrap();
+        super::modprobe(&shell, "/home/foo/my mod.ko", &[], false).unwrap();
+        super::modprobe(&shell, "/home/foo/kvm-intel.ko", &[], false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo modprobe loop max_loop=64", None, false, false, false, false),
+            SshCommand::make_cmd("lsmod", None, false, false, false, false),
+            SshCommand::make_cmd("sudo insmod '/home/foo/my mod.ko'", None, false, false, false, false),
+            SshCommand::make_cmd("lsmod", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_rmmod() {
+        let shell = TestSshShell::new();
+        super::rmmod(&shell, "msr", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo rmmod msr", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_spawn_detached() {
+        let shell = TestSshShell::new();
+        let pid = super::spawn_detached(
+            &shell,
+            cmd!("./memcached -p 11211").cwd("memcached"),
+            "mc log",
+            false,
+        )
+        .unwrap();
+        assert_eq!(pid, 4321);
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd(
+                "nohup bash -c 'cd memcached ; ./memcached -p 11211' > 'mc log' 2>&1 < /dev/null & echo $!",
+                None, false, false, false, /* no_pty = */ true
+            ),
+        };
+    }
+
+    #[test]
+    fn test_wait_until() {
+        let shell = TestSshShell::new();
+        super::wait_until(
+            &shell,
+            cmd!("test -e /var/run/foo.pid"),
+            Duration::from_secs(0),
+            Duration::from_secs(0),
+            false,
+        )
+        .unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("test -e /var/run/foo.pid", None, false, true, false, false),
+        };
+    }
+
+    #[test]
+    fn test_wait_until_timeout() {
+        let shell = TestSshShell::failing_on(&["nc -z"]);
+        assert!(matches!(
+            super::wait_until(
+                &shell,
+                cmd!("nc -z localhost 8080"),
+                Duration::from_secs(0),
+                Duration::from_secs(0),
+                false,
+            ),
+            Err(SshError::Timeout { .. })
+        ));
+    }
+
+    #[test]
+    fn test_pgrep() {
+        let shell = TestSshShell::new();
+        assert_eq!(
+            super::pgrep(&shell, "my daemon", false).unwrap(),
+            vec![1234, 5678]
+        );
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("pgrep 'my daemon'", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_pgrep_no_match() {
+        let shell = TestSshShell::failing_on(&["pgrep"]);
+        assert!(super::pgrep(&shell, "memcached", false).unwrap().is_empty());
+
+        let shell = TestSshShell {
+            fail_exit: 2,
+            ..TestSshShell::failing_on(&["pgrep"])
+        };
+        assert!(super::pgrep(&shell, "memcached", false).is_err());
+    }
+
+    #[test]
+    fn test_pkill() {
+        let shell = TestSshShell::failing_on(&["pkill"]);
+        super::pkill(&shell, "memcached", None, false).unwrap();
+        super::pkill(&shell, "memcached", Some(9), false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("pkill memcached", None, false, false, false, false),
+            SshCommand::make_cmd("pkill -9 memcached", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_lsmod_contains() {
+        let shell = TestSshShell::new();
+        assert!(super::lsmod_contains(&shell, "msr", false).unwrap());
+        assert!(super::lsmod_contains(&shell, "kvm-intel", false).unwrap());
+        assert!(!super::lsmod_contains(&shell, "cpuid", false).unwrap());
+        assert!(!super::lsmod_contains(&shell, "Module", false).unwrap());
+    }
+
+    #[test]
+    fn test_set_hugepages() {
+        let shell = TestSshShell::new();
+        super::set_hugepages(&shell, 2048, 512, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd(
+                "echo 512 | sudo tee /sys/kernel/mm/hugepages/hugepages-2048kB/nr_hugepages",
+                None, /* use_bash = */ true, false, false, false
+            ),
+            SshCommand::make_cmd(
+                "cat /sys/kernel/mm/hugepages/hugepages-2048kB/nr_hugepages \
+                 /sys/kernel/mm/hugepages/hugepages-2048kB/free_hugepages",
+                None, false, false, false, false
+            ),
+        }
+    }
+
+    #[test]
+    fn test_get_hugepages() {
+        let shell = TestSshShell::new();
+        assert_eq!(
+            super::get_hugepages(&shell, 2048, false).unwrap(),
+            (512, 100)
+        );
+        assert_eq!(super::get_hugepages(&shell, 2048, true).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_set_transparent_hugepages_unknown() {
+        let shell = TestSshShell::new();
+        assert!(matches!(
+            super::set_transparent_hugepages(&shell, "sometimes", false),
+            Err(SshError::IoError { ref error }) if error.kind() == std::io::ErrorKind::InvalidInput
+        ));
+        assert!(shell.commands.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_transparent_hugepages() {
+        let shell = TestSshShell::new();
+        let mode = super::get_transparent_hugepages(&shell, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /sys/kernel/mm/transparent_hugepage/enabled", None, false, false, false, false),
+        }
+        assert_eq!(mode, "never");
+    }
+
+    #[test]
+    fn test_parse_bracketed_choice() {
+        assert_eq!(
+            super::parse_bracketed_choice("[none] mq-deadline\n"),
+            Some("none")
+        );
+        assert_eq!(
+            super::parse_bracketed_choice("always madvise [never]"),
+            Some("never")
+        );
+        assert_eq!(super::parse_bracketed_choice("none"), None);
+        assert_eq!(super::parse_bracketed_choice(""), None);
+    }
+
+    #[test]
+    fn test_swapoff() {
+        assert_eq!(
+            super::swapoff("foobar"),
+            SshCommand::make_cmd("sudo swapoff foobar", None, false, false, false, false)
+        );
+    }
+
+    #[test]
+    fn test_swapon() {
+        assert_eq!(
+            super::swapon("foobar"),
+            SshCommand::make_cmd("sudo swapon foobar", None, false, false, false, false)
+        );
+    }
+
+    #[test]
+    fn test_add_to_group() {
+        assert_eq!(
+            super::add_to_group("foobar"),
+            SshCommand::make_cmd(
+                "sudo usermod -aG foobar `whoami`",
+                None,
+                true, // use_bash
+                false,
+                false,
+                false,
+            )
+        );
+    }
 
-            self.commands.lock().unwrap().push(cmd);
+    #[test]
+    fn test_write_gpt() {
+        assert_eq!(
+            super::write_gpt("foobar"),
+            SshCommand::make_cmd(
+                "sudo parted -a optimal foobar -s -- mklabel gpt",
+                None,
+                false,
+                false,
+                false,
+                false,
+            )
+        );
+    }
 
-            let stdout = match short_cmd {
-                FakeCommand::Blkid => "UUID=1fb958bf-de7e-428a-a0b7-a598f22e96fa\n".into(),
-                FakeCommand::Kname1 => "KNAME\nfoobar\nfoo\nbar\nbaz\n".into(),
-                FakeCommand::Kname2 => "KNAME\nfoobar\nfoo\nbar\nbaz\nsdb\nsdc".into(),
-                FakeCommand::Kname3 => "KNAME\nsdb".into(),
-                FakeCommand::Kname4 => "KNAME\nfoo".into(),
-                FakeCommand::KnameMountpoint => {
-                    "KNAME MOUNTPOINT\nfoobar\nfoo  /mnt/foo\nbar  /mnt/bar\nbaz\nsdb\nsdc".into()
-                }
-                FakeCommand::Size1 => "SIZE\n477G".into(),
-                FakeCommand::Size2 => "SIZE\n400G".into(),
-                FakeCommand::Size3 => "SIZE\n500G".into(),
-                FakeCommand::Unknown => String::new(),
-            };
+    #[test]
+    fn test_create_partition() {
+        assert_eq!(
+            super::create_partition("foobar"),
+            SshCommand::make_cmd(
+                "sudo parted -a optimal foobar -s -- mkpart primary 0% 100%",
+                None,
+                false,
+                false,
+                false,
+                false,
+            )
+        );
+    }
 
-            info!("Output: {}", stdout);
+    #[test]
+    fn test_bind_mount() {
+        assert_eq!(
+            super::bind_mount("/data/set 1", "/mnt"),
+            SshCommand::make_cmd(
+                "sudo mount --bind '/data/set 1' /mnt",
+                None,
+                false,
+                false,
+                false,
+                false,
+            )
+        );
+    }
 
-            Ok(SshOutput {
-                stdout,
-                stderr: String::new(),
-            })
-        }
+    #[test]
+    fn test_ensure_bind_mount() {
+        let shell = TestSshShell::failing_on(&["mountpoint"]);
+        super::ensure_bind_mount(&shell, "/data", "/mnt", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("mountpoint -q /mnt", None, false, false, false, false),
+            SshCommand::make_cmd("sudo mount --bind /data /mnt", None, false, false, false, false),
+        };
+    }
 
-        fn duplicate(&self) -> Result<Self, SshError> {
-            Ok(self.clone())
-        }
+    #[test]
+    fn test_ensure_bind_mount_already_mounted() {
+        let shell = TestSshShell::new();
+        super::ensure_bind_mount(&shell, "/data", "/mnt", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("mountpoint -q /mnt", None, false, false, false, false),
+        };
+    }
 
-        fn reconnect(&mut self) -> Result<(), SshError> {
-            info!("Test reconnect");
+    #[test]
+    fn test_path_exists() {
+        let shell = TestSshShell::new();
+        assert!(super::path_exists(&shell, "/my file", false).unwrap());
+        assert!(super::is_file(&shell, "/my file", false).unwrap());
+        assert!(super::is_dir(&shell, "/tmp", false).unwrap());
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("test -e '/my file'", None, false, false, false, false),
+            SshCommand::make_cmd("test -f '/my file'", None, false, false, false, false),
+            SshCommand::make_cmd("test -d /tmp", None, false, false, false, false),
+        };
+    }
 
-            Ok(())
-        }
+    #[test]
+    fn test_path_exists_missing() {
+        let shell = TestSshShell::failing_on(&["test"]);
+        assert!(!super::path_exists(&shell, "/foo", false).unwrap());
+        assert!(!super::is_file(&shell, "/foo", false).unwrap());
+        assert!(!super::is_dir(&shell, "/foo", false).unwrap());
     }
 
-    macro_rules! expect_cmd_sequence {
-        ($shell:expr) => {
-            assert!($shell.commands.is_empty());
+    #[test]
+    fn test_read_file() {
+        let shell = TestSshShell::new();
+        assert_eq!(
+            super::read_file(&shell, "my results.txt", false).unwrap(),
+            ""
+        );
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat 'my results.txt'", None, false, false, false, true),
         };
-        ($shell:expr, $($cmd:expr),+ $(,)?) => {
-            let expected: &[SshCommand] = &[$($cmd),+];
-            let locked = $shell.commands.lock().unwrap();
+    }
 
-            if locked.len() != expected.len() {
-                panic!("Number of commands run does not match expected number: \n Expected: {:#?}\nActual:  {:#?}====\n", expected, locked);
-            }
+    #[test]
+    fn test_write_file() {
+        let shell = TestSshShell::new();
+        super::write_file(&shell, "my config", "FOO=\"$HOME\"\n", false, false).unwrap();
+        super::write_file(&shell, "/etc/foo.conf", "bar\n", true, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("tee 'my config' > /dev/null", None, false, false, false, false)
+                .stdin("FOO=\"$HOME\"\n"),
+            SshCommand::make_cmd("sudo tee /etc/foo.conf > /dev/null", None, false, false, false, false)
+                .stdin("bar\n"),
+        };
+    }
 
-            let mut fail = false;
-            let mut message = "Actual commands did not match expected commands: \n".to_owned();
+    #[test]
+    fn test_ensure_line_in_file() {
+        let shell = TestSshShell::new();
+        super::ensure_line_in_file(&shell, "msr", "/etc/modules", true, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo grep -qxF -- msr /etc/modules", None, false, false, false, false),
+        };
+    }
 
-            for (expected, actual) in expected.iter().zip(locked.iter()) {
-                if expected != actual {
-                    fail = true;
-                    message.push_str(&format!("\nExpected: {:#?}\nActual:  {:#?}\n=====\n", expected, actual));
-                }
-            }
+    #[test]
+    fn test_ensure_line_in_file_missing() {
+        let shell = TestSshShell::failing_on(&["grep -qxF"]);
+        super::ensure_line_in_file(
+            &shell,
+            "export PATH=\"$HOME/bin:$PATH\"",
+            ".bashrc",
+            false,
+            false,
+        )
+        .unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd(
+                "grep -qxF -- 'export PATH=\"$HOME/bin:$PATH\"' .bashrc",
+                None, false, false, false, false
+            ),
+            SshCommand::make_cmd(
+                "printf '%s\\n' 'export PATH=\"$HOME/bin:$PATH\"' | tee -a .bashrc > /dev/null",
+                None, false, false, false, false
+            ),
+        };
+    }
 
-            if fail {
-                panic!("{}", message);
-            }
+    #[test]
+    fn test_git_clone() {
+        let shell = TestSshShell::failing_on(&["test -d"]);
+        super::git_clone(
+            &shell,
+            "https://github.com/mark-i-m/spurs",
+            "my repo",
+            Some("master"),
+            Some(1),
+            false,
+        )
+        .unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("test -d 'my repo'/.git", None, false, false, false, false),
+            SshCommand::make_cmd(
+                "git clone --branch master --depth 1 https://github.com/mark-i-m/spurs 'my repo'",
+                None, false, false, false, false
+            ),
         };
     }
 
     #[test]
-    fn test_set_cpu_scaling_governor() {
-        assert_eq!(
-            super::set_cpu_scaling_governor("foobar"),
+    fn test_git_clone_existing() {
+        let shell = TestSshShell::new();
+        super::git_clone(
+            &shell,
+            "https://github.com/mark-i-m/spurs",
+            "spurs",
+            None,
+            None,
+            false,
+        )
+        .unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("test -d spurs/.git", None, false, false, false, false),
+            SshCommand::make_cmd("git -C spurs pull", None, false, false, false, false),
+        };
+    }
+
+    const DOWNLOAD_CMD: &str = "if command -v curl >/dev/null; then \
+        curl -fL -o foo.tar https://example.com/foo.tar; \
+        else wget -O foo.tar https://example.com/foo.tar; fi";
+
+    #[test]
+    fn test_download() {
+        let shell = TestSshShell::new();
+        super::download(
+            &shell,
+            "https://example.com/foo.tar",
+            "foo.tar",
+            Some("E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855"),
+            false,
+        )
+        .unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd(DOWNLOAD_CMD, None, /* use_bash = */ true, false, false, false),
+            SshCommand::make_cmd("sha256sum foo.tar", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_download_checksum_mismatch() {
+        let shell = TestSshShell::new();
+        let result = super::download(
+            &shell,
+            "https://example.com/foo.tar",
+            "foo.tar",
+            Some("0000"),
+            false,
+        );
+        assert!(matches!(result, Err(SshError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn test_tar_extract() {
+        let shell = TestSshShell::new();
+        super::tar_extract(&shell, "linux-5.4.tar.xz", "/tmp/src", false).unwrap();
+        super::tar_extract(&shell, "data.tar", "my data", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("mkdir -p /tmp/src", None, false, false, false, false),
+            SshCommand::make_cmd("tar xJf linux-5.4.tar.xz -C /tmp/src", None, false, false, false, false),
+            SshCommand::make_cmd("mkdir -p 'my data'", None, false, false, false, false),
+            SshCommand::make_cmd("tar xf data.tar -C 'my data'", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_tar_create() {
+        let shell = TestSshShell::new();
+        super::tar_create(&shell, "out.tar.gz", &["a", "b c"], false).unwrap();
+        super::tar_create(&shell, "out.tar.bz2", &["a"], false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("tar czf out.tar.gz a 'b c'", None, false, false, false, false),
+            SshCommand::make_cmd("tar cjf out.tar.bz2 a", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_systemctl() {
+        let shell = TestSshShell::new();
+        super::systemctl(&shell, super::SystemctlAction::Restart, "redis", false).unwrap();
+        super::systemctl(&shell, super::SystemctlAction::Status, "redis", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo systemctl restart redis", None, false, false, false, false),
+            SshCommand::make_cmd("sudo systemctl status redis", None, false, /* allow_error = */ true, false, false),
+        };
+    }
+
+    #[test]
+    fn test_set_kernel_cmdline() {
+        let shell = TestSshShell::new();
+        super::set_kernel_cmdline(&shell, &["transparent_hugepage=never", "quiet"], false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("grep '^GRUB_CMDLINE_LINUX=' /etc/default/grub", None, false, false, false, false),
             SshCommand::make_cmd(
-                "sudo cpupower frequency-set -g foobar".into(),
-                None,
-                false,
-                false,
-                false,
-                false,
-            )
+                "sudo sed -i 's|^GRUB_CMDLINE_LINUX=.*|GRUB_CMDLINE_LINUX=\"quiet transparent_hugepage=never\"|' /etc/default/grub",
+                None, false, false, false, false
+            ),
+            SshCommand::make_cmd(
+                "if command -v update-grub >/dev/null; then sudo update-grub; \
+                 else sudo grub2-mkconfig -o /boot/grub2/grub.cfg; fi",
+                None, /* use_bash = */ true, false, false, false
+            ),
+        };
+    }
+
+    #[test]
+    fn test_merge_kernel_cmdline() {
+        assert_eq!(
+            super::merge_kernel_cmdline("", &["isolcpus=1", "nosmt"]),
+            "isolcpus=1 nosmt"
+        );
+        assert_eq!(
+            super::merge_kernel_cmdline("quiet  isolcpus=1 splash", &["isolcpus=2-3", "quiet"]),
+            "quiet isolcpus=2-3 splash"
+        );
+
+        // Repeated parameters are all kept.
+        assert_eq!(
+            super::merge_kernel_cmdline(
+                "quiet console=tty0",
+                &["console=ttyS0,115200", "console=tty0"]
+            ),
+            "quiet console=tty0 console=ttyS0,115200"
+        );
+        assert_eq!(
+            super::merge_kernel_cmdline(
+                "console=tty0 console=ttyS0 quiet",
+                &["console=ttyS1", "quiet"]
+            ),
+            "console=tty0 console=ttyS0 quiet console=ttyS1"
+        );
+        assert_eq!(
+            super::merge_kernel_cmdline(
+                "quiet",
+                &[
+                    "hugepagesz=1G",
+                    "hugepages=4",
+                    "hugepagesz=2M",
+                    "hugepages=512"
+                ]
+            ),
+            "quiet hugepagesz=1G hugepages=4 hugepagesz=2M hugepages=512"
         );
+        assert_eq!(
+            super::merge_kernel_cmdline(
+                "hugepagesz=1G hugepages=4 hugepagesz=2M hugepages=512",
+                &[
+                    "hugepagesz=1G",
+                    "hugepages=4",
+                    "hugepagesz=2M",
+                    "hugepages=512"
+                ]
+            ),
+            "hugepagesz=1G hugepages=4 hugepagesz=2M hugepages=512"
+        );
+    }
+
+    #[test]
+    fn test_format_partition_as_ext4() {
+        let shell = TestSshShell::new();
+        super::format_partition_as_ext4(&shell, false, "/dev/foobar", "/mnt/point/", "me").unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("lsblk", None, false, false, false, false),
+            SshCommand::make_cmd("sudo mkfs.ext4 /dev/foobar", None, false, false, false, false),
+            SshCommand::make_cmd("mkdir -p /tmp/tmp_mnt", None, false, false, false, false),
+            SshCommand::make_cmd("sudo mount -t ext4 /dev/foobar /tmp/tmp_mnt", None, false, false, false, false),
+            SshCommand::make_cmd("sudo chown me /tmp/tmp_mnt", None, false, false, false, false),
+            SshCommand::make_cmd("rsync -a /mnt/point// /tmp/tmp_mnt/", None, false, false, false, false),
+            SshCommand::make_cmd("sync", None, false, false, false, false),
+            SshCommand::make_cmd("sudo umount /tmp/tmp_mnt", None, false, false, false, false),
+            SshCommand::make_cmd("sudo mount -t ext4 /dev/foobar /mnt/point/", None, false, false, false, false),
+            SshCommand::make_cmd("sudo chown me /mnt/point/", None, false, false, false, false),
+            SshCommand::make_cmd("sudo blkid -o export /dev/foobar | grep '^UUID='", None, /* use_bash = */ true, false, false, false),
+            SshCommand::make_cmd(r#"echo "UUID=1fb958bf-de7e-428a-a0b7-a598f22e96fa    /mnt/point/    ext4    defaults    0    1" | sudo tee -a /etc/fstab"#, None, false, false, false, false),
+            SshCommand::make_cmd("lsblk", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_format_partition() {
+        let shell = TestSshShell::new();
+        super::format_partition(
+            &shell,
+            "/dev/foobar",
+            "/mnt/point",
+            "me",
+            super::FsType::Xfs,
+            false,
+        )
+        .unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("lsblk", None, false, false, false, false),
+            SshCommand::make_cmd("sudo mkfs.xfs -f /dev/foobar", None, false, false, false, false),
+            SshCommand::make_cmd("mkdir -p /tmp/tmp_mnt", None, false, false, false, false),
+            SshCommand::make_cmd("sudo mount -t xfs /dev/foobar /tmp/tmp_mnt", None, false, false, false, false),
+            SshCommand::make_cmd("sudo chown me /tmp/tmp_mnt", None, false, false, false, false),
+            SshCommand::make_cmd("rsync -a /mnt/point/ /tmp/tmp_mnt/", None, false, false, false, false),
+            SshCommand::make_cmd("sync", None, false, false, false, false),
+            SshCommand::make_cmd("sudo umount /tmp/tmp_mnt", None, false, false, false, false),
+            SshCommand::make_cmd("sudo mount -t xfs /dev/foobar /mnt/point", None, false, false, false, false),
+            SshCommand::make_cmd("sudo chown me /mnt/point", None, false, false, false, false),
+            SshCommand::make_cmd("sudo blkid -o export /dev/foobar | grep '^UUID='", None, /* use_bash = */ true, false, false, false),
+            SshCommand::make_cmd(r#"echo "UUID=1fb958bf-de7e-428a-a0b7-a598f22e96fa    /mnt/point    xfs    defaults    0    1" | sudo tee -a /etc/fstab"#, None, false, false, false, false),
+            SshCommand::make_cmd("lsblk", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_mount_and_add_fstab_entry() {
+        let shell = TestSshShell::new();
+        super::mkfs_ext4(&shell, "/dev/sdb1", false).unwrap();
+        super::mount(&shell, "/dev/sdb1", "/mnt/my data", "ext4", false).unwrap();
+        super::add_fstab_entry(&shell, "/dev/sdb1", "/mnt/data", "ext4", false).unwrap();
+        super::mkfs(&shell, "/dev/sdc", super::FsType::Btrfs, false).unwrap();
+        super::add_fstab_entry(&shell, "/dev/sdc", "/mnt/btrfs", "btrfs", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo mkfs.ext4 /dev/sdb1", None, false, false, false, false),
+            SshCommand::make_cmd("sudo mount -t ext4 /dev/sdb1 '/mnt/my data'", None, false, false, false, false),
+            SshCommand::make_cmd(r#"echo "/dev/sdb1    /mnt/data    ext4    defaults    0    1" | sudo tee -a /etc/fstab"#, None, false, false, false, false),
+            SshCommand::make_cmd("sudo mkfs.btrfs -f /dev/sdc", None, false, false, false, false),
+            SshCommand::make_cmd(r#"echo "/dev/sdc    /mnt/btrfs    btrfs    defaults    0    0" | sudo tee -a /etc/fstab"#, None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_ensure_dir() {
+        let shell = TestSshShell::new();
+        super::ensure_dir(
+            &shell,
+            "/data/my results",
+            Some("alice:users"),
+            Some("755"),
+            false,
+        )
+        .unwrap();
+        super::ensure_dir(&shell, "results", None, Some("g+w"), false).unwrap();
+        super::ensure_dir(&shell, "results", None, None, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("mkdir -p '/data/my results'", None, false, false, false, false),
+            SshCommand::make_cmd(
+                "sudo chown alice:users '/data/my results'",
+                None, false, false, false, false
+            ),
+            SshCommand::make_cmd("sudo chmod 755 '/data/my results'", None, false, false, false, false),
+            SshCommand::make_cmd("mkdir -p results", None, false, false, false, false),
+            SshCommand::make_cmd("chmod g+w results", None, false, false, false, false),
+            SshCommand::make_cmd("mkdir -p results", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_create_user() {
+        let shell = TestSshShell::new();
+        super::create_user(&shell, "alice", true, Some("/bin/bash"), false).unwrap();
+        super::create_user(&shell, "bob", false, None, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo useradd -m -s /bin/bash alice", None, false, false, false, false),
+            SshCommand::make_cmd("sudo useradd bob", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_create_user_already_exists() {
+        let shell = TestSshShell {
+            fail_exit: 9,
+            ..TestSshShell::failing_on(&["useradd"])
+        };
+        super::create_user(&shell, "alice", true, None, false).unwrap();
+
+        let shell = TestSshShell::failing_on(&["useradd"]);
+        assert!(super::create_user(&shell, "alice", true, None, false).is_err());
+    }
+
+    #[test]
+    fn test_create_user_invalid() {
+        let shell = TestSshShell::new();
+        for username in &["alice; rm -rf /", "-r", ""] {
+            assert!(matches!(
+                super::create_user(&shell, username, true, None, false),
+                Err(SshError::IoError { ref error }) if error.kind() == std::io::ErrorKind::InvalidInput
+            ));
+            assert!(matches!(
+                super::add_authorized_key(&shell, "ssh-ed25519 AAAAC3Nz me@host", Some(username), false),
+                Err(SshError::IoError { ref error }) if error.kind() == std::io::ErrorKind::InvalidInput
+            ));
+        }
+        assert!(shell.commands.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_set_user_password() {
+        let shell = TestSshShell::new();
+        super::set_user_password(&shell, "alice", "hunter2", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo chpasswd", None, false, false, false, false)
+                .stdin("alice:hunter2\n"),
+        };
+    }
+
+    #[test]
+    fn test_add_authorized_key() {
+        let shell = TestSshShell::failing_on(&["grep -qxF"]);
+        super::add_authorized_key(&shell, "ssh-ed25519 AAAAC3Nz me@host\n", None, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("mkdir -p ~/.ssh", None, false, false, false, false),
+            SshCommand::make_cmd("chmod 700 ~/.ssh", None, false, false, false, false),
+            SshCommand::make_cmd("grep -qxF 'ssh-ed25519 AAAAC3Nz me@host' ~/.ssh/authorized_keys", None, false, false, false, false),
+            SshCommand::make_cmd("echo 'ssh-ed25519 AAAAC3Nz me@host' | tee -a ~/.ssh/authorized_keys", None, /* use_bash = */ true, false, false, false),
+            SshCommand::make_cmd("chmod 600 ~/.ssh/authorized_keys", None, false, false, false, false),
+        };
     }
 
     #[test]
-    fn test_swapoff() {
-        assert_eq!(
-            super::swapoff("foobar"),
-            SshCommand::make_cmd(
-                "sudo swapoff foobar".into(),
-                None,
-                false,
-                false,
-                false,
-                false,
-            )
-        );
+    fn test_add_authorized_key_already_present() {
+        let shell = TestSshShell::new();
+        super::add_authorized_key(&shell, "ssh-ed25519 AAAAC3Nz me@host", Some("alice"), false)
+            .unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo mkdir -p ~alice/.ssh", None, false, false, false, false),
+            SshCommand::make_cmd("sudo chmod 700 ~alice/.ssh", None, false, false, false, false),
+            SshCommand::make_cmd("sudo grep -qxF 'ssh-ed25519 AAAAC3Nz me@host' ~alice/.ssh/authorized_keys", None, false, false, false, false),
+            SshCommand::make_cmd("sudo chmod 600 ~alice/.ssh/authorized_keys", None, false, false, false, false),
+            SshCommand::make_cmd("sudo chown -R alice: ~alice/.ssh", None, false, false, false, false),
+        };
     }
 
     #[test]
-    fn test_swapon() {
-        assert_eq!(
-            super::swapon("foobar"),
+    fn test_set_hostname() {
+        let shell = TestSshShell::new();
+        super::set_hostname(&shell, "node-1.cluster", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
             SshCommand::make_cmd(
-                "sudo swapon foobar".into(),
-                None,
-                false,
-                false,
-                false,
-                false,
-            )
-        );
+                "if command -v hostnamectl >/dev/null; then sudo hostnamectl set-hostname node-1.cluster; \
+                 else sudo hostname node-1.cluster; fi",
+                None, /* use_bash = */ true, false, false, false
+            ),
+            SshCommand::make_cmd("echo node-1.cluster | sudo tee /etc/hostname", None, false, false, false, false),
+            SshCommand::make_cmd(r"sudo sed -i 's/^127\.0\.1\.1\s.*/127.0.1.1\tnode-1.cluster/' /etc/hosts", None, false, false, false, false),
+        };
     }
 
     #[test]
-    fn test_add_to_group() {
-        assert_eq!(
-            super::add_to_group("foobar"),
-            SshCommand::make_cmd(
-                "sudo usermod -aG foobar `whoami`".into(),
-                None,
-                true, // use_bash
-                false,
-                false,
-                false,
-            )
-        );
+    fn test_set_hostname_invalid() {
+        let shell = TestSshShell::new();
+        for hostname in &["node 1; rm -rf /", "-node1", ""] {
+            assert!(matches!(
+                super::set_hostname(&shell, hostname, false),
+                Err(SshError::IoError { ref error }) if error.kind() == std::io::ErrorKind::InvalidInput
+            ));
+        }
+        assert!(shell.commands.lock().unwrap().is_empty());
     }
 
     #[test]
-    fn test_write_gpt() {
-        assert_eq!(
-            super::write_gpt("foobar"),
-            SshCommand::make_cmd(
-                "sudo parted -a optimal foobar -s -- mklabel gpt".into(),
-                None,
-                false,
-                false,
-                false,
-                false,
-            )
-        );
+    fn test_setup_swap_device() {
+        let shell = TestSshShell::failing_on(&["grep -qxF"]);
+        super::setup_swap_device(&shell, "/dev/disk/by-label/my swap", true, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo mkswap '/dev/disk/by-label/my swap'", None, false, false, false, false),
+            SshCommand::make_cmd("sudo swapon '/dev/disk/by-label/my swap'", None, false, false, false, false),
+            SshCommand::make_cmd("sudo blkid -o export '/dev/disk/by-label/my swap' | grep '^UUID='", None, /* use_bash = */ true, false, false, false),
+            SshCommand::make_cmd("sudo grep -qxF -- 'UUID=1fb958bf-de7e-428a-a0b7-a598f22e96fa    none    swap    sw    0    0' /etc/fstab", None, false, false, false, false),
+            SshCommand::make_cmd("printf '%s\\n' 'UUID=1fb958bf-de7e-428a-a0b7-a598f22e96fa    none    swap    sw    0    0' | sudo tee -a /etc/fstab > /dev/null", None, false, false, false, false),
+        };
     }
 
     #[test]
-    fn test_create_partition() {
-        assert_eq!(
-            super::create_partition("foobar"),
-            SshCommand::make_cmd(
-                "sudo parted -a optimal foobar -s -- mkpart primary 0% 100%".into(),
-                None,
-                false,
-                false,
-                false,
-                false,
-            )
-        );
+    fn test_setup_swap_device_already_in_fstab() {
+        let shell = TestSshShell::new();
+        super::setup_swap_device(&shell, "/dev/sdb", true, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo mkswap /dev/sdb", None, false, false, false, false),
+            SshCommand::make_cmd("sudo swapon /dev/sdb", None, false, false, false, false),
+            SshCommand::make_cmd("sudo blkid -o export /dev/sdb | grep '^UUID='", None, /* use_bash = */ true, false, false, false),
+            SshCommand::make_cmd("sudo grep -qxF -- 'UUID=1fb958bf-de7e-428a-a0b7-a598f22e96fa    none    swap    sw    0    0' /etc/fstab", None, false, false, false, false),
+        };
     }
 
     #[test]
-    fn test_format_partition_as_ext4() {
-        let mut shell = TestSshShell::new();
-        super::format_partition_as_ext4(&mut shell, false, "/dev/foobar", "/mnt/point/", "me")
-            .unwrap();
+    fn test_lvm_create_vg() {
+        let shell = TestSshShell::new();
+        super::lvm_create_vg(&shell, "data", &["/dev/sdb", "/dev/sdc"], false).unwrap();
+        super::lvm_create_vg(&shell, "my data", &["/dev/disk/by-label/a b"], false).unwrap();
         expect_cmd_sequence! {
             shell,
-            SshCommand::make_cmd("lsblk", None, false, false, false, false),
-            SshCommand::make_cmd("sudo mkfs.ext4 /dev/foobar", None, false, false, false, false),
-            SshCommand::make_cmd("mkdir -p /tmp/tmp_mnt", None, false, false, false, false),
-            SshCommand::make_cmd("sudo mount -t ext4 /dev/foobar /tmp/tmp_mnt", None, false, false, false, false),
-            SshCommand::make_cmd("sudo chown me /tmp/tmp_mnt", None, false, false, false, false),
-            SshCommand::make_cmd("rsync -a /mnt/point// /tmp/tmp_mnt/", None, false, false, false, false),
-            SshCommand::make_cmd("sync", None, false, false, false, false),
-            SshCommand::make_cmd("sudo umount /tmp/tmp_mnt", None, false, false, false, false),
-            SshCommand::make_cmd("sudo mount -t ext4 /dev/foobar /mnt/point/", None, false, false, false, false),
-            SshCommand::make_cmd("sudo chown me /mnt/point/", None, false, false, false, false),
-            SshCommand::make_cmd("sudo blkid -o export /dev/foobar | grep '^UUID='", None, /* use_bash = */ true, false, false, false),
-            SshCommand::make_cmd(r#"echo "UUID=1fb958bf-de7e-428a-a0b7-a598f22e96fa    /mnt/point/    ext4    defaults    0    1" | sudo tee -a /etc/fstab"#, None, false, false, false, false),
-            SshCommand::make_cmd("lsblk", None, false, false, false, false),
+            SshCommand::make_cmd("sudo pvcreate /dev/sdb /dev/sdc", None, false, false, false, false),
+            SshCommand::make_cmd("sudo vgcreate data /dev/sdb /dev/sdc", None, false, false, false, false),
+            SshCommand::make_cmd("sudo pvcreate '/dev/disk/by-label/a b'", None, false, false, false, false),
+            SshCommand::make_cmd("sudo vgcreate 'my data' '/dev/disk/by-label/a b'", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_lvm_create_lv() {
+        let shell = TestSshShell::new();
+        super::lvm_create_lv(&shell, "data", "scratch", "100G", false).unwrap();
+        super::lvm_create_lv(&shell, "data", "rest", "100%FREE", false).unwrap();
+        super::lvm_create_lv(&shell, "my data", "x; rm -rf /", "1G", false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo lvcreate -L 100G -n scratch data", None, false, false, false, false),
+            SshCommand::make_cmd("sudo lvcreate -l 100%FREE -n rest data", None, false, false, false, false),
+            SshCommand::make_cmd("sudo lvcreate -L 1G -n 'x; rm -rf /' 'my data'", None, false, false, false, false),
         };
     }
 
+    #[cfg(feature = "serde_json")]
     #[test]
     fn test_get_partitions() {
-        let mut shell = TestSshShell::new();
-        let partitions = super::get_partitions(&mut shell, "/dev/foobar", false).unwrap();
+        let shell = TestSshShell::new();
+        let partitions = super::get_partitions(&shell, "/dev/foobar", false).unwrap();
         expect_cmd_sequence! {
             shell,
-            SshCommand::make_cmd("lsblk -o KNAME /dev/foobar", None, false, false, false, false),
+            SshCommand::make_cmd("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT /dev/foobar", None, false, false, false, false),
         }
         assert_eq!(
             {
@@ -563,61 +3982,165 @@ mod test {
         );
     }
 
+    #[cfg(feature = "serde_json")]
     #[test]
     fn test_get_unpartitioned_devices() {
-        let mut shell = TestSshShell::new();
-        let devs = super::get_unpartitioned_devs(&mut shell, false).unwrap();
+        let shell = TestSshShell::new();
+        let devs = super::get_unpartitioned_devs(&shell, false).unwrap();
         expect_cmd_sequence! {
             shell,
-            SshCommand::make_cmd("lsblk -o KNAME", None, false, false, false, false),
-            SshCommand::make_cmd("lsblk -o KNAME /dev/bar", None, false, false, false, false),
-            SshCommand::make_cmd("lsblk -o KNAME /dev/baz", None, false, false, false, false),
-            SshCommand::make_cmd("lsblk -o KNAME /dev/foo", None, false, false, false, false),
-            SshCommand::make_cmd("lsblk -o KNAME /dev/foobar", None, false, false, false, false),
-            SshCommand::make_cmd("lsblk -o KNAME /dev/sdb", None, false, false, false, false),
-            SshCommand::make_cmd("lsblk -o KNAME /dev/sdc", None, false, false, false, false),
+            SshCommand::make_cmd("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT", None, false, false, false, false),
         }
         assert_eq!(
             {
                 let mut set = std::collections::HashSet::new();
                 set.insert("sdb".into());
                 set.insert("sdc".into());
+                set.insert("dm-0".into());
                 set
             },
             devs
         );
     }
 
+    #[cfg(feature = "serde_json")]
     #[test]
     fn test_get_mounted_devs() {
-        let mut shell = TestSshShell::new();
-        let devs = super::get_mounted_devs(&mut shell, false).unwrap();
+        let shell = TestSshShell::new();
+        let devs = super::get_mounted_devs(&shell, false).unwrap();
         expect_cmd_sequence! {
             shell,
-            SshCommand::make_cmd("lsblk -o KNAME,MOUNTPOINT", None, false, false, false, false),
+            SshCommand::make_cmd("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT", None, false, false, false, false),
         }
         assert_eq!(
             vec![
                 ("foo".to_owned(), "/mnt/foo".to_owned()),
-                ("bar".to_owned(), "/mnt/bar".to_owned())
+                ("bar".to_owned(), "/mnt/bar".to_owned()),
+                ("dm-0".to_owned(), "/mnt/my data".to_owned()),
             ],
             devs
         );
     }
 
+    #[test]
+    fn test_get_mount_point() {
+        let shell = TestSshShell::new();
+        assert_eq!(
+            super::get_mount_point(&shell, "/dev/foo", false).unwrap(),
+            Some("/mnt/foo".to_owned())
+        );
+        assert!(super::is_mounted(&shell, "/dev/foo", false).unwrap());
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("findmnt -n -o TARGET /dev/foo", None, false, true, false, false),
+            SshCommand::make_cmd("findmnt -n -o TARGET /dev/foo", None, false, true, false, false),
+        };
+    }
+
+    #[test]
+    fn test_unmount() {
+        let shell = TestSshShell::new();
+        super::unmount(&shell, "/mnt/foo", false, false, false).unwrap();
+        super::unmount(&shell, "/dev/foo", true, true, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("findmnt -n -o TARGET /mnt/foo", None, false, true, false, false),
+            SshCommand::make_cmd("sudo umount /mnt/foo", None, false, false, false, false),
+            SshCommand::make_cmd("findmnt -n -o TARGET /dev/foo", None, false, true, false, false),
+            SshCommand::make_cmd("sudo fuser -km /dev/foo", None, false, true, false, false),
+            SshCommand::make_cmd("sudo umount -f -l /dev/foo", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_unmount_not_mounted() {
+        let shell = TestSshShell::failing_on(&["findmnt"]);
+        super::unmount(&shell, "/mnt/foo", true, false, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("findmnt -n -o TARGET /mnt/foo", None, false, true, false, false),
+        };
+    }
+
+    #[test]
+    fn test_get_mount_point_not_mounted() {
+        let shell = TestSshShell::failing_on(&["findmnt"]);
+        assert_eq!(
+            super::get_mount_point(&shell, "/dev/sdb1", false).unwrap(),
+            None
+        );
+        assert!(!super::is_mounted(&shell, "/dev/sdb1", false).unwrap());
+    }
+
+    #[test]
+    fn test_get_disk_usage() {
+        let shell = TestSshShell::new();
+        let usage = super::get_disk_usage(&shell, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("df -B1 --output=source,size,used,avail,pcent,target", None, false, false, false, false),
+        }
+        assert_eq!(
+            usage,
+            vec![
+                super::DiskUsage {
+                    source: "/dev/sda2".into(),
+                    size: 270553174016,
+                    used: 19648032768,
+                    avail: 83573497856,
+                    percent_used: Some(20),
+                    mountpoint: "/".into(),
+                },
+                super::DiskUsage {
+                    source: "none".into(),
+                    size: 0,
+                    used: 0,
+                    avail: 0,
+                    percent_used: None,
+                    mountpoint: "/proc/fake".into(),
+                },
+                super::DiskUsage {
+                    source: "/dev/sdb1".into(),
+                    size: 470974464,
+                    used: 379809792,
+                    avail: 54689792,
+                    percent_used: Some(88),
+                    mountpoint: "/mnt/my data".into(),
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde_json")]
     #[test]
     fn test_get_dev_sizes() {
-        let mut shell = TestSshShell::new();
-        let devs = super::get_dev_sizes(&mut shell, vec!["sda", "sdb", "sdc"], false).unwrap();
+        let shell = TestSshShell::new();
+        let devs = super::get_dev_sizes(&shell, vec!["sda", "sdb", "sdc"], false).unwrap();
         expect_cmd_sequence! {
             shell,
-            SshCommand::make_cmd("lsblk -o SIZE /dev/sda", None, false, false, false, false),
-            SshCommand::make_cmd("lsblk -o SIZE /dev/sdb", None, false, false, false, false),
-            SshCommand::make_cmd("lsblk -o SIZE /dev/sdc", None, false, false, false, false),
+            SshCommand::make_cmd("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT /dev/sda", None, false, false, false, false),
+            SshCommand::make_cmd("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT /dev/sdb", None, false, false, false, false),
+            SshCommand::make_cmd("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT /dev/sdc", None, false, false, false, false),
         }
         assert_eq!(vec!["477G".to_owned(), "400G".into(), "500G".into()], devs);
     }
 
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_get_dev_sizes_unexpected_output() {
+        let shell = TestSshShell::new();
+        let result = super::get_dev_sizes(&shell, vec!["sda", "bogus"], false);
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT /dev/sda", None, false, false, false, false),
+            SshCommand::make_cmd("lsblk -J -o KNAME,TYPE,SIZE,MOUNTPOINT /dev/bogus", None, false, false, false, false),
+        }
+        assert!(matches!(
+            result,
+            Err(SshError::UnexpectedOutput { ref output, .. }) if output == r#"{"blockdevices": []}"#
+        ));
+    }
+
     mod test_escape_for_bash {
         use super::super::escape_for_bash;
 
@@ -636,7 +4159,7 @@ mod test {
 
             let out = Command::new("bash")
                 .arg("-c")
-                .arg(&format!("echo {}", escape_for_bash(TEST_STRING)))
+                .arg(format!("echo {}", escape_for_bash(TEST_STRING)))
                 .output()
                 .unwrap();
             let out = String::from_utf8(out.stdout).unwrap();
@@ -654,13 +4177,275 @@ mod test {
         assert_eq!(port, 2303);
     }
 
+    #[test]
+    fn test_assert_idempotent() {
+        let shell = TestSshShell::new();
+        let idempotent =
+            super::assert_idempotent(&shell, cmd!("touch /foo"), cmd!("ls /foo"), false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("touch /foo", None, false, false, false, false),
+            SshCommand::make_cmd("ls /foo", None, false, false, false, false),
+            SshCommand::make_cmd("touch /foo", None, false, false, false, false),
+            SshCommand::make_cmd("ls /foo", None, false, false, false, false),
+        };
+        assert!(idempotent);
+    }
+
+    #[test]
+    fn test_assert_idempotent_changed() {
+        let shell = TestSshShell::new();
+        let idempotent =
+            super::assert_idempotent(&shell, cmd!("touch /foo"), cmd!("date +%s%N"), false)
+                .unwrap();
+        assert!(!idempotent);
+    }
+
+    #[test]
+    fn test_detect_distro() {
+        let shell = TestSshShell::new();
+        assert_eq!(
+            super::detect_distro(&shell, false).unwrap(),
+            super::Distro::Centos
+        );
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /etc/os-release", None, false, false, false, true),
+        };
+    }
+
+    #[test]
+    fn test_install() {
+        let shell = TestSshShell::new();
+        super::install(&shell, &["gcc", "make"], false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /etc/os-release", None, false, false, false, true),
+            SshCommand::make_cmd("sudo yum install -y gcc make", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_install_on() {
+        let shell = TestSshShell::new();
+        super::install_on(&shell, super::Distro::Arch, &["gcc"], false).unwrap();
+        super::install_on(&shell, super::Distro::Ubuntu, &["gcc"], false).unwrap();
+        super::install_on(&shell, super::Distro::Other(String::new()), &["gcc"], true).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("sudo pacman -S --noconfirm gcc", None, false, false, false, false),
+            SshCommand::make_cmd("sudo apt-get update", None, false, false, false, false),
+            SshCommand::make_cmd("sudo apt-get -y install gcc", None, false, false, false, false),
+        };
+
+        assert!(matches!(
+            super::install_on(&shell, super::Distro::Other("gentoo".into()), &["gcc"], false),
+            Err(SshError::IoError { ref error }) if error.kind() == std::io::ErrorKind::Unsupported
+        ));
+    }
+
+    #[test]
+    fn test_ensure_installed() {
+        let shell = TestSshShell::new();
+        super::ensure_installed(&shell, &["build-essential"], &["gcc", "make"], false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /etc/os-release", None, false, false, false, true),
+            SshCommand::make_cmd("sudo yum install -y build-essential", None, false, false, false, false),
+            SshCommand::make_cmd("command -v gcc", None, false, false, false, false),
+            SshCommand::make_cmd("command -v make", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_ensure_installed_missing() {
+        let shell = TestSshShell::failing_on(&["command -v perf"]);
+        assert!(matches!(
+            super::ensure_installed(&shell, &["linux-tools"], &["perf", "gcc"], false),
+            Err(SshError::NonZeroExit { .. })
+        ));
+        assert_eq!(shell.commands.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_parse_os_release() {
+        use super::{parse_os_release, Distro};
+
+        assert_eq!(
+            parse_os_release("NAME=\"Ubuntu\"\nID=ubuntu\nID_LIKE=debian\n"),
+            Distro::Ubuntu
+        );
+        assert_eq!(
+            parse_os_release("ID=linuxmint\nID_LIKE=\"ubuntu debian\"\n"),
+            Distro::Ubuntu
+        );
+        assert_eq!(parse_os_release("ID=debian\n"), Distro::Debian);
+        assert_eq!(parse_os_release("ID=fedora\n"), Distro::Fedora);
+        assert_eq!(
+            parse_os_release("ID=\"manjaro\"\nID_LIKE=arch\n"),
+            Distro::Arch
+        );
+        assert_eq!(parse_os_release("ID=alpine\n"), Distro::Alpine);
+        assert_eq!(
+            parse_os_release("ID=opensuse-leap\nID_LIKE=\"suse opensuse\"\n"),
+            Distro::Other("opensuse-leap".into())
+        );
+        assert_eq!(parse_os_release(""), Distro::Other("".into()));
+    }
+
+    #[test]
+    fn test_machine_fingerprint() {
+        let shell = TestSshShell::failing_on(&["lscpu"]);
+        let info = super::machine_fingerprint(&shell, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /etc/os-release", None, false, false, false, false),
+            SshCommand::make_cmd("uname -a", None, false, false, false, false),
+            SshCommand::make_cmd("lscpu", None, false, false, false, false),
+            SshCommand::make_cmd("cat /proc/meminfo", None, false, false, false, false),
+            SshCommand::make_cmd("lsblk -J", None, false, false, false, false),
+        };
+        assert!(info.os_release.is_some());
+        assert!(info.kernel.is_some());
+        assert!(info.cpu.is_none());
+        assert!(info.memory.is_some());
+        assert!(info.block_devices.is_some());
+    }
+
     #[test]
     fn test_reboot() {
         let mut shell = TestSshShell::new();
-        super::reboot(&mut shell, false).unwrap();
+        super::reboot(&mut shell, Duration::from_secs(0), false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /proc/sys/kernel/random/boot_id", None, false, false, false, true),
+            SshCommand::make_cmd("sudo reboot", None, false, false, false, false),
+            SshCommand::make_cmd("cat /proc/sys/kernel/random/boot_id", None, false, false, false, true),
+            SshCommand::make_cmd("whoami", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_reboot_rejected() {
+        let mut shell = TestSshShell::failing_on(&["sudo reboot"]);
+        assert!(matches!(
+            super::reboot(&mut shell, Duration::from_secs(0), false),
+            Err(SshError::NonZeroExit { .. })
+        ));
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /proc/sys/kernel/random/boot_id", None, false, false, false, true),
+            SshCommand::make_cmd("sudo reboot", None, false, false, false, false),
+        };
+    }
+
+    #[test]
+    fn test_rsync_remote_path() {
+        assert_eq!(
+            super::rsync_remote_path("markm@10.0.0.1", "/data"),
+            "markm@10.0.0.1:/data"
+        );
+        assert_eq!(
+            super::rsync_remote_path("markm@fe80::1", "/data"),
+            "markm@[fe80::1]:/data"
+        );
+    }
+
+    #[test]
+    fn test_rsync_args() {
+        assert_eq!(
+            super::rsync_args("ssh -i key -p 22", "results/", "markm@host:/data", false),
+            vec![
+                "-a",
+                "-e",
+                "ssh -i key -p 22",
+                "results/",
+                "markm@host:/data"
+            ]
+        );
+        assert_eq!(
+            super::rsync_args("ssh -i key -p 22", "markm@host:/data", "results", true),
+            vec![
+                "-a",
+                "--delete",
+                "-e",
+                "ssh -i key -p 22",
+                "markm@host:/data",
+                "results"
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_parse_iperf_bandwidth() {
+        let json = r#"{
+            "start": { "connected": [] },
+            "intervals": [],
+            "end": {
+                "sum_sent": { "bytes": 11811160064, "bits_per_second": 9448928051.2 },
+                "sum_received": { "bytes": 11808014336, "bits_per_second": 9446411468.8 }
+            }
+        }"#;
+        assert_eq!(super::parse_iperf_bandwidth(json), Some(9446411468.8));
+        assert_eq!(
+            super::parse_iperf_bandwidth(r#"{ "error": "unable to connect" }"#),
+            None
+        );
+        assert_eq!(super::parse_iperf_bandwidth("not json"), None);
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let reference = TestSshShell::new();
+        let skews = super::clock_skew(&reference, &[reference.clone(), reference.clone()]).unwrap();
+        assert_eq!(skews.len(), 2);
+        assert!((skews[0] - 10.0).abs() < 1.0, "{:?}", skews);
+        assert!((skews[1] - 20.0).abs() < 1.0, "{:?}", skews);
+    }
+
+    #[test]
+    fn test_sync_time() {
+        let shell = TestSshShell::new();
+        super::sync_time(&shell, false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd(
+                "if command -v chronyc >/dev/null; then sudo chronyc makestep; \
+                 else sudo ntpdate pool.ntp.org; fi",
+                None, /* use_bash = */ true, false, false, false
+            ),
+        };
+    }
+
+    #[test]
+    fn test_wait_for_reboot() {
+        let mut shell = TestSshShell::new();
+        super::wait_for_reboot(&mut shell, "0", Duration::from_secs(0), false).unwrap();
+        expect_cmd_sequence! {
+            shell,
+            SshCommand::make_cmd("cat /proc/sys/kernel/random/boot_id", None, false, false, false, true),
+        };
+    }
+
+    #[test]
+    fn test_wait_for_reboot_unchanged() {
+        let mut shell = TestSshShell::new();
+        assert!(matches!(
+            super::wait_for_reboot(&mut shell, "1", Duration::from_secs(0), false),
+            Err(SshError::NotRebooted { .. })
+        ));
+    }
+
+    #[test]
+    fn test_reboot_with_limit() {
+        let mut shell = TestSshShell::new();
+        super::reboot_with_limit(&mut shell, Duration::from_secs(0), 5, false).unwrap();
         expect_cmd_sequence! {
             shell,
+            SshCommand::make_cmd("cat /proc/sys/kernel/random/boot_id", None, false, false, false, true),
             SshCommand::make_cmd("sudo reboot", None, false, false, false, false),
+            SshCommand::make_cmd("cat /proc/sys/kernel/random/boot_id", None, false, false, false, true),
             SshCommand::make_cmd("whoami", None, false, false, false, false),
         };
     }
@@ -1,6 +1,6 @@
 //! Functionality specific to Ubuntu.
 
-use spurs::{cmd, SshCommand};
+use spurs::{cmd, escape_for_bash, Execute, SshCommand, SshError};
 
 /// Install the given .deb packages via `dpkg`. Requires `sudo` priveleges.
 pub fn dpkg_install(pkg: &str) -> SshCommand {
@@ -12,6 +12,41 @@ pub fn apt_install(pkgs: &[&str]) -> SshCommand {
     cmd!("sudo apt-get -y install {}", pkgs.join(" "))
 }
 
+/// Add the given apt repository (e.g. `ppa:deadsnakes/ppa`) via `add-apt-repository`. Requires
+/// `sudo` priveleges.
+pub fn add_apt_repository(repo: &str) -> SshCommand {
+    cmd!("sudo add-apt-repository -y {}", escape_for_bash(repo))
+}
+
+/// Update the list of available packages via `apt-get update`. Requires `sudo` priveleges.
+pub fn apt_update() -> SshCommand {
+    cmd!("sudo apt-get update")
+}
+
+/// Add the given apt repository and then update the list of available packages, so that packages
+/// from the repository can be installed. Requires `sudo` priveleges.
+pub fn add_repo_and_update(
+    shell: &impl Execute,
+    repo: &str,
+    dry_run: bool,
+) -> Result<(), SshError> {
+    shell.run(add_apt_repository(repo).dry_run(dry_run))?;
+    shell.run(apt_update().dry_run(dry_run))?;
+
+    Ok(())
+}
+
+/// Remove the given list of packages via `apt-get remove`. Requires `sudo` priveleges.
+pub fn apt_remove(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo apt-get -y remove {}", pkgs.join(" "))
+}
+
+/// Remove the given list of packages and their configuration files via `apt-get remove --purge`.
+/// Requires `sudo` priveleges.
+pub fn apt_purge(pkgs: &[&str]) -> SshCommand {
+    cmd!("sudo apt-get -y remove --purge {}", pkgs.join(" "))
+}
+
 #[cfg(test)]
 mod test {
     use spurs::SshCommand;
@@ -20,8 +55,16 @@ mod test {
     fn test_dpkg_install() {
         assert_eq!(
             super::dpkg_install("foobar"),
+            SshCommand::make_cmd("sudo dpkg -i foobar", None, false, false, false, false),
+        );
+    }
+
+    #[test]
+    fn test_apt_install() {
+        assert_eq!(
+            super::apt_install(&["foobar"]),
             SshCommand::make_cmd(
-                "sudo dpkg -i foobar".into(),
+                "sudo apt-get -y install foobar",
                 None,
                 false,
                 false,
@@ -32,11 +75,26 @@ mod test {
     }
 
     #[test]
-    fn test_apt_install() {
+    fn test_apt_remove() {
         assert_eq!(
-            super::apt_install(&["foobar"]),
+            super::apt_remove(&["foo", "bar"]),
+            SshCommand::make_cmd(
+                "sudo apt-get -y remove foo bar",
+                None,
+                false,
+                false,
+                false,
+                false,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_apt_purge() {
+        assert_eq!(
+            super::apt_purge(&["foobar"]),
             SshCommand::make_cmd(
-                "sudo apt-get -y install foobar".into(),
+                "sudo apt-get -y remove --purge foobar",
                 None,
                 false,
                 false,
@@ -45,4 +103,51 @@ mod test {
             ),
         );
     }
+
+    #[test]
+    fn test_add_apt_repository() {
+        assert_eq!(
+            super::add_apt_repository("ppa:deadsnakes/ppa"),
+            SshCommand::make_cmd(
+                "sudo add-apt-repository -y ppa:deadsnakes/ppa",
+                None,
+                false,
+                false,
+                false,
+                false,
+            ),
+        );
+        assert_eq!(
+            super::add_apt_repository("deb http://example.com/ubuntu focal main"),
+            SshCommand::make_cmd(
+                "sudo add-apt-repository -y 'deb http://example.com/ubuntu focal main'",
+                None,
+                false,
+                false,
+                false,
+                false,
+            ),
+        );
+    }
+
+    #[test]
+    fn test_apt_update() {
+        assert_eq!(
+            super::apt_update(),
+            SshCommand::make_cmd("sudo apt-get update", None, false, false, false, false),
+        );
+    }
+
+    #[test]
+    fn test_add_repo_and_update() {
+        let shell = crate::test::TestSshShell::new();
+        super::add_repo_and_update(&shell, "ppa:deadsnakes/ppa", false).unwrap();
+        assert_eq!(
+            *shell.commands.lock().unwrap(),
+            vec![
+                super::add_apt_repository("ppa:deadsnakes/ppa"),
+                super::apt_update()
+            ],
+        );
+    }
 }